@@ -1,9 +1,10 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt;
-use cosmwasm_std::{to_binary, Addr, BankMsg, Coin, CosmosMsg, MessageInfo, StdError, StdResult,
+use cosmwasm_std::{to_binary, Addr, BankMsg, Coin, CosmosMsg, MessageInfo, StdResult,
   Uint128, WasmMsg};
 use cw20::{Cw20ExecuteMsg};
+use crate::error::ContractError;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
@@ -69,27 +70,11 @@ impl fmt::Display for Asset {
 }
 
 impl Asset{
-  pub fn assert_sent_native_token_balance(&self, message_info: &MessageInfo) -> StdResult<()> {
+  pub fn assert_sent_native_token_balance(&self, message_info: &MessageInfo) -> Result<(), ContractError> {
     if let AssetInfo::NativeToken { denom } = &self.info {
-      match message_info.funds.iter().find(|x| x.denom == *denom) {
-        Some(coin) => {
-          if self.amount == coin.amount {
-            Ok(())
-          } else {
-            Err(StdError::generic_err("Native token balance mismatch between the argument and the transferred"))
-          }
-        }
-        None => {
-          if self.amount.is_zero() {
-              Ok(())
-          } else {
-            Err(StdError::generic_err("Native token balance mismatch between the argument and the transferred"))
-          }
-        }
-      }
-    } else {
-      Ok(())
+      assert_exact_payment(native_payment(message_info, denom)?, self.amount)?;
     }
+    Ok(())
   }
 
   pub fn into_msg(self, recipient: Addr) -> StdResult<CosmosMsg> {
@@ -100,7 +85,7 @@ impl Asset{
         contract_addr: contract_addr.to_string(),
         msg: to_binary(&Cw20ExecuteMsg::Transfer {
           recipient: recipient.to_string(),
-          amount : amount
+          amount
         })?,
         funds: vec![],
       })),
@@ -113,4 +98,81 @@ impl Asset{
       })),
     }
   }
-}
\ No newline at end of file
+}
+
+/// Amount of `denom` attached to the message, zero if it was not sent.
+pub fn sent_native_amount(message_info: &MessageInfo, denom: &str) -> Result<Uint128, ContractError> {
+  let mut sent = Uint128::zero();
+  for coin in message_info.funds.iter() {
+    if coin.amount.is_zero() {
+      return Err(ContractError::ZeroFunds {});
+    }
+    if coin.denom == denom {
+      sent += coin.amount;
+    }
+  }
+  Ok(sent)
+}
+
+/// The one coin paying for a native purchase. Further denoms would otherwise
+/// be left in the contract, so they are refused.
+pub fn single_native_coin(message_info: &MessageInfo) -> Result<&Coin, ContractError> {
+  match message_info.funds.as_slice() {
+    [] => Err(ContractError::NoFunds {}),
+    [coin] if coin.amount.is_zero() => Err(ContractError::ZeroFunds {}),
+    [coin] => Ok(coin),
+    _ => Err(ContractError::MultipleDenoms {}),
+  }
+}
+
+/// Amount paid in `denom`, which must be the only coin attached.
+pub fn native_payment(message_info: &MessageInfo, denom: &str) -> Result<Uint128, ContractError> {
+  let coin = single_native_coin(message_info)?;
+  if coin.denom != denom {
+    return Err(ContractError::UnexpectedDenom { denom: coin.denom.clone() });
+  }
+  Ok(coin.amount)
+}
+
+/// Fixed prices are paid exactly, whatever the asset.
+pub fn assert_exact_payment(paid: Uint128, price: Uint128) -> Result<(), ContractError> {
+  if paid < price {
+    return Err(ContractError::NotEnoughFunds {});
+  }
+  if paid > price {
+    return Err(ContractError::TooMuchFunds {});
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use cosmwasm_std::testing::mock_info;
+
+  fn coin(amount: u128, denom: &str) -> Coin {
+    Coin { denom: denom.to_string(), amount: Uint128::new(amount) }
+  }
+
+  #[test]
+  fn native_funds() {
+    let juno = Asset { info: AssetInfo::NativeToken { denom: "ujuno".to_string() }, amount: Uint128::new(100) };
+    let check = |funds: &[Coin]| juno.assert_sent_native_token_balance(&mock_info("buyer", funds));
+    assert!(check(&[coin(100, "ujuno")]).is_ok());
+    assert!(matches!(check(&[coin(100, "ujuno"), coin(5, "uatom")]), Err(ContractError::MultipleDenoms {})));
+    assert!(matches!(check(&[coin(100, "uatom")]), Err(ContractError::UnexpectedDenom { denom }) if denom == "uatom"));
+    assert!(matches!(check(&[]), Err(ContractError::NoFunds {})));
+    assert!(matches!(check(&[coin(99, "ujuno")]), Err(ContractError::NotEnoughFunds {})));
+    assert!(matches!(check(&[coin(101, "ujuno")]), Err(ContractError::TooMuchFunds {})));
+    assert!(matches!(check(&[coin(0, "ujuno")]), Err(ContractError::ZeroFunds {})));
+    let token = Asset { info: AssetInfo::Token { contract_addr: "token".to_string() }, amount: Uint128::new(100) };
+    assert!(token.assert_sent_native_token_balance(&mock_info("buyer", &[])).is_ok());
+
+    let pay = |funds: &[Coin]| native_payment(&mock_info("buyer", funds), "ujuno");
+    assert_eq!(pay(&[coin(100, "ujuno")]).unwrap(), Uint128::new(100));
+    assert!(matches!(pay(&[]), Err(ContractError::NoFunds {})));
+    assert!(matches!(pay(&[coin(0, "ujuno")]), Err(ContractError::ZeroFunds {})));
+    assert!(matches!(pay(&[coin(100, "uatom")]), Err(ContractError::UnexpectedDenom { denom }) if denom == "uatom"));
+    assert!(matches!(pay(&[coin(100, "ujuno"), coin(100, "uatom")]), Err(ContractError::MultipleDenoms {})));
+  }
+}
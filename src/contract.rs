@@ -1,21 +1,31 @@
 use cosmwasm_std::{
-    entry_point, to_binary, Coin, Deps, DepsMut, Env, MessageInfo, Response,from_binary,Binary,
-    StdResult, Uint128,CosmosMsg,WasmMsg,Decimal,BankMsg,Order
+    entry_point, to_binary, Addr, Deps, DepsMut, Env, MessageInfo, Response,from_binary,Binary,
+    StdResult, Uint128,CosmosMsg,WasmMsg,Decimal,Order,Storage,StdError,HexBinary,QuerierWrapper,Api
 };
 
-use cw2::set_contract_version;
-use cw20::{ Cw20ExecuteMsg,Cw20ReceiveMsg};
-use cw721::{Cw721ReceiveMsg, Cw721ExecuteMsg};
+use cw2::{get_contract_version, set_contract_version};
+use cw20::{Cw20ReceiveMsg, Cw20ExecuteMsg};
+use cw_storage_plus::Bound;
+use cw721::{Cw721ReceiveMsg, Cw721ExecuteMsg, Cw721QueryMsg, NftInfoResponse, OwnerOfResponse};
 
+use crate::asset::{Asset as PaymentAsset, AssetInfo, assert_exact_payment, native_payment, sent_native_amount, single_native_coin};
 use crate::error::ContractError;
-use crate::msg::{ ExecuteMsg, InstantiateMsg, QueryMsg,SellNft, BuyNft};
-use crate::state::{State,CONFIG,Offering, OFFERINGS,UserInfo, MEMBERS,SALEHISTORY,PRICEINFO,SaleInfo,PriceInfo, COLLECTIONINFO, CollectionInfo, TOKENADDRESS, TVL, TvlInfo};
-use crate::package::QueryOfferingsResult;
+use crate::merkle::{is_valid_root, verify_proof};
+use crate::msg::{ ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg,SellNft, BuyNft, Cw721HookMsg, Cw20HookMsg, StartAuction};
+use crate::state::{State,CONFIG,Asset,Offering, OFFERINGS,LEGACY_OFFERINGS,UserInfo, MEMBERS,SALEHISTORY,PRICEINFO,SaleInfo,PriceInfo,PriceDecay, COLLECTIONINFO, CollectionInfo, TOKENADDRESS, TVL, TvlInfo, ACCRUED_FEES, PENDING_PAYMENTS, REFERRAL_VOLUME, RoyaltySource, AUCTIONS, Auction, Bid, OFFERS, Offer, COLLECTION_BIDS, CollectionBid, Trait, Metadata, BUNDLES, LAST_BUNDLE_ID, Bundle, BundleItem, SWAPS, LAST_SWAP_ID, Swap, NftRef, MINT_TEMPLATES, MintTemplate, LAUNCHES, LAUNCH_MINTS, LAUNCH_TOKEN_PREFIX, Launch, LaunchPhase};
+use crate::package::{QueryOfferingsResult, QueryAuctionsResult, QueryOffersResult, QueryCollectionBidsResult, QueryBundlesResult, QuerySwapsResult, OracleQueryMsg, OraclePriceResponse, QueryMintTemplatesResult, Cw2981QueryMsg, Cw2981Query, RoyaltiesInfoResponse};
 
 
 const CONTRACT_NAME: &str = "NFTea_Market_Place";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
+/// Highest protocol fee the owner can set, in percent.
+const MAX_PROTOCOL_FEE_PERCENT: u64 = 10;
+/// Highest referral cut the owner can set, in basis points.
+const MAX_REFERRAL_BPS: u64 = 500;
+
 #[entry_point]
 pub fn instantiate(
     deps: DepsMut,
@@ -27,6 +37,10 @@ pub fn instantiate(
     let state = State {
         owner:msg.owner,
         new:true,
+        oracle:None,
+        protocol_fee:Decimal::zero(),
+        treasury:None,
+        referral_bps:0,
     };
     CONFIG.save(deps.storage,&state)?;
     Ok(Response::default())
@@ -42,10 +56,43 @@ pub fn execute(
     match msg {
     ExecuteMsg::ReceiveNft(msg) =>execute_receive_nft(deps,env,info,msg),
     ExecuteMsg::Receive(msg) =>execute_receive(deps,env,info,msg),
-    ExecuteMsg::BuyNft { offering_id,nft_address } =>execute_buy_nft(deps,env,info,offering_id,nft_address),
+    ExecuteMsg::BuyNft { offering_id,nft_address,token_id,max_price,proof,referrer } =>execute_buy_nft(deps,env,info,offering_id,nft_address,token_id,max_price,proof,referrer),
     ExecuteMsg::WithdrawNft { offering_id,nft_address } => execute_withdraw(deps,env,info,offering_id,nft_address),
+    ExecuteMsg::UpdatePrice { nft_address, offering_id, list_price } => execute_update_price(deps,env,info,nft_address,offering_id,list_price),
+    ExecuteMsg::PruneExpired { nft_address, start_after, limit } => execute_prune_expired(deps,env,nft_address,start_after,limit),
+    ExecuteMsg::ListNft { nft_address, token_id, price } => execute_list_nft(deps,env,info,nft_address,token_id,price),
+    ExecuteMsg::PruneStale { nft_address, offering_ids } => execute_prune_stale(deps,env,nft_address,offering_ids),
+    ExecuteMsg::BuyMany { items, max_total } => execute_buy_many_native(deps,env,info,items,max_total),
+    ExecuteMsg::AddMintTemplate { nft_address, token_id, token_uri, price } => execute_add_mint_template(deps,env,info,nft_address,token_id,token_uri,price),
+    ExecuteMsg::RemoveMintTemplate { nft_address, template_id } => execute_remove_mint_template(deps,env,info,nft_address,template_id),
+    ExecuteMsg::BuyMint { nft_address, template_id } => execute_buy_native_mint(deps,env,info,nft_address,template_id),
+    ExecuteMsg::SetLaunch { nft_address, phases, supply_cap, base_token_uri } => execute_set_launch(deps,env,info,nft_address,phases,supply_cap,base_token_uri),
+    ExecuteMsg::LaunchMint { nft_address, quantity, proof } => execute_launch_native_mint(deps,env,info,nft_address,quantity,proof),
+    ExecuteMsg::PlaceBid { nft_address, auction_id } => execute_place_native_bid(deps,env,info,nft_address,auction_id),
+    ExecuteMsg::SettleAuction { nft_address, auction_id } => execute_settle_auction(deps,env,nft_address,auction_id),
+    ExecuteMsg::CancelAuction { nft_address, auction_id } => execute_cancel_auction(deps,env,info,nft_address,auction_id),
+    ExecuteMsg::MakeOffer { nft_address, token_id, expires_at } => execute_make_native_offer(deps,env,info,nft_address,token_id,expires_at),
+    ExecuteMsg::AcceptOffer { nft_address, offering_id, offer_id } => execute_accept_offer_for_listing(deps,env,info,nft_address,offering_id,offer_id),
+    ExecuteMsg::CancelOffer { nft_address, token_id, offer_id } => execute_cancel_offer(deps,env,info,nft_address,token_id,offer_id),
+    ExecuteMsg::PlaceCollectionBid { nft_address, price, quantity, traits } => execute_place_native_collection_bid(deps,env,info,nft_address,price,quantity,traits.unwrap_or_default()),
+    ExecuteMsg::CancelCollectionBid { nft_address, bid_id } => execute_cancel_collection_bid(deps,env,info,nft_address,bid_id),
+    ExecuteMsg::CreateBundle { price, items } => execute_create_bundle(deps,env,info,price,items),
+    ExecuteMsg::BuyBundle { bundle_id } => execute_buy_native_bundle(deps,env,info,bundle_id),
+    ExecuteMsg::CancelBundle { bundle_id } => execute_cancel_bundle(deps,env,info,bundle_id),
+    ExecuteMsg::CreateSwap { offered_nfts, wanted_nfts, wanted_funds, counterparty } => execute_create_swap(deps,env,info,offered_nfts,wanted_nfts,wanted_funds,counterparty),
+    ExecuteMsg::TakeSwap { swap_id } => execute_take_swap(deps,env,info,swap_id),
+    ExecuteMsg::CancelSwap { swap_id } => execute_cancel_swap(deps,env,info,swap_id),
     ExecuteMsg::AddTokenAddress { symbol, address }  => execute_token_address(deps,env,info,symbol,address),
     ExecuteMsg::ChangeOwner { address } =>execute_change_owner(deps,env,info,address),
+    ExecuteMsg::SetOracle { address } => execute_set_oracle(deps,env,info,address),
+    ExecuteMsg::SetProtocolFee { nft_address, fee } => execute_set_protocol_fee(deps,env,info,nft_address,fee),
+    ExecuteMsg::SetTreasury { address } => execute_set_treasury(deps,env,info,address),
+    ExecuteMsg::WithdrawFees { denoms } => execute_withdraw_fees(deps,env,info,denoms),
+    ExecuteMsg::SetPullPayments { nft_address, enabled } => execute_set_pull_payments(deps,env,info,nft_address,enabled),
+    ExecuteMsg::SetReferralFee { nft_address, bps } => execute_set_referral_fee(deps,env,info,nft_address,bps),
+    ExecuteMsg::SetRoyaltySource { nft_address, cw2981 } => execute_set_royalty_source(deps,env,info,nft_address,cw2981),
+    ExecuteMsg::Claim { assets } => execute_claim(deps,env,info.sender.to_string(),assets),
+    ExecuteMsg::ClaimFor { address, assets } => execute_claim(deps,env,address,assets),
     ExecuteMsg::AddCollection { royalty_portion, members,nft_address ,offering_id,sale_id} =>execute_add_collection(deps,env,info,royalty_portion,members,nft_address,offering_id,sale_id),
     ExecuteMsg::UpdateCollection { royalty_portion, members,nft_address } =>execute_update_collection(deps,env,info,royalty_portion,members,nft_address),
     ExecuteMsg:: FixNft{address,token_id} =>execute_fix_nft(deps,env,info,address,token_id),
@@ -59,1130 +106,5511 @@ pub fn execute(
 
 fn execute_receive_nft(
     deps: DepsMut,
-    _env:Env,
+    env:Env,
+    info: MessageInfo,
+    rcv_msg: Cw721ReceiveMsg,
+)-> Result<Response, ContractError> {
+    match from_binary::<Cw721HookMsg>(&rcv_msg.msg) {
+        Ok(Cw721HookMsg::StartAuction(msg)) => execute_start_auction(deps,env,info,rcv_msg.sender,rcv_msg.token_id,msg),
+        Ok(Cw721HookMsg::AcceptOffer { offer_id }) => {
+            let seller = deps.api.addr_validate(&rcv_msg.sender)?;
+            let messages = accept_offer(deps.storage,deps.api,deps.querier,&env,info.sender.as_ref(),&rcv_msg.token_id,offer_id,seller)?;
+            Ok(Response::new()
+                .add_attribute("action", "accept_offer")
+                .add_attribute("offer_id", offer_id.to_string())
+                .add_messages(messages))
+        }
+        Ok(Cw721HookMsg::FillCollectionBid { bid_id }) => execute_fill_collection_bid(deps,env,info,rcv_msg.sender,rcv_msg.token_id,bid_id),
+        Ok(Cw721HookMsg::DepositBundle { bundle_id }) => execute_deposit_bundle(deps,env,info,rcv_msg.sender,rcv_msg.token_id,bundle_id),
+        Ok(Cw721HookMsg::DepositSwap { swap_id }) => execute_deposit_swap(deps,env,info,rcv_msg.sender,rcv_msg.token_id,swap_id),
+        // Anything else is a plain fixed-price listing
+        Err(_) => execute_sell_nft(deps,env,info,rcv_msg),
+    }
+}
+
+fn execute_sell_nft(
+    deps: DepsMut,
+    env:Env,
     info: MessageInfo,
     rcv_msg: Cw721ReceiveMsg,
 )-> Result<Response, ContractError> {
+    let nft_address = info.sender.to_string();
+
+    let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
 
-    let collection_info = COLLECTIONINFO.may_load(deps.storage, &info.sender.to_string())?;
+    let msg:SellNft = from_binary(&rcv_msg.msg)?;
+    validate_list_price(&msg.list_price)?;
+    validate_price_decay(&msg.list_price, &msg.price_decay)?;
+    let alt_prices = msg.alt_prices.unwrap_or_default();
+    validate_alt_prices(&msg.list_price, &alt_prices)?;
+    if let Some(peg_unit) = &msg.peg_unit {
+        if peg_unit.is_empty() {
+            return Err(ContractError::InvalidPrice {});
+        }
+        if CONFIG.load(deps.storage)?.oracle.is_none() {
+            return Err(ContractError::OracleNotSet {});
+        }
+    }
+    if msg.reserved_root.as_ref().is_some_and(|root| !is_valid_root(root)) {
+        return Err(ContractError::InvalidMerkleRoot {});
+    }
 
-    if collection_info == None{
-        return Err(ContractError::WrongNFTContractError { });
+    if let Some(expires_at) = msg.expires_at {
+        if expires_at <= env.block.time.seconds() || expires_at <= msg.starts_at.unwrap_or_default() {
+            return Err(ContractError::InvalidListingTime {});
+        }
     }
 
-    let mut collection_info = collection_info.unwrap();
-    
+    collection_info.offering_id += 1;
+    collection_info.last_offering_id += 1;
+    let offering_id = collection_info.last_offering_id;
 
-    let msg:SellNft = from_binary(&rcv_msg.msg)?;
-    let nft_address = info.sender.to_string();
-    
-    collection_info.offering_id = collection_info.offering_id + 1;
-   
     COLLECTIONINFO.save(deps.storage, &nft_address,&collection_info)?;
 
     let off = Offering {
         token_id: rcv_msg.token_id.clone(),
         seller: deps.api.addr_validate(&rcv_msg.sender)?.to_string(),
-        list_price: msg.list_price.clone(),
+        list_price: msg.list_price,
+        starts_at: msg.starts_at,
+        expires_at: msg.expires_at,
+        price_decay: msg.price_decay,
+        reserved_for: msg.reserved_for
+            .map(|addr| deps.api.addr_validate(&addr).map(|addr| addr.to_string()))
+            .transpose()?,
+        non_custodial: false,
+        alt_prices,
+        peg_unit: msg.peg_unit,
+        reserved_root: msg.reserved_root,
     };
 
-    
-  
-    OFFERINGS.save(deps.storage, (&nft_address,&collection_info.offering_id.to_string()), &off)?;
-    Ok(Response::default())
+    OFFERINGS.save(deps.storage, (&nft_address,offering_id), &off)?;
+    Ok(Response::new()
+        .add_attribute("action", "list_nft")
+        .add_attribute("offering_id", offering_id.to_string()))
 }
 
-fn execute_receive(
+/// Lists a token without escrow. The seller keeps it and approves the
+/// marketplace, which transfers it on their behalf at purchase.
+fn execute_list_nft(
     deps: DepsMut,
     env:Env,
     info: MessageInfo,
-    rcv_msg: Cw20ReceiveMsg,
+    nft_address: String,
+    token_id: String,
+    price: Asset
 )-> Result<Response, ContractError> {
-    let _state = CONFIG.load(deps.storage)?;
+    let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+    validate_list_price(&price)?;
+
+    if !is_listing_approved(deps.as_ref(), &env, &nft_address, &token_id, info.sender.as_str()) {
+        return Err(ContractError::StaleListing {});
+    }
+
+    collection_info.offering_id += 1;
+    collection_info.last_offering_id += 1;
+    let offering_id = collection_info.last_offering_id;
+
+    COLLECTIONINFO.save(deps.storage, &nft_address,&collection_info)?;
 
-    let token_symbol = TOKENADDRESS.may_load(deps.storage, &info.sender.to_string())?;
+    OFFERINGS.save(deps.storage, (&nft_address,offering_id), &Offering {
+        token_id,
+        seller: info.sender.to_string(),
+        list_price: price,
+        starts_at: None,
+        expires_at: None,
+        price_decay: None,
+        reserved_for: None,
+        non_custodial: true,
+        alt_prices: vec![],
+        peg_unit: None,
+        reserved_root: None,
+    })?;
+    Ok(Response::new()
+        .add_attribute("action", "list_nft")
+        .add_attribute("offering_id", offering_id.to_string()))
+}
 
-    if token_symbol == None{
-        return Err(ContractError::WrongTokenContractError {  })
+fn execute_receive(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    rcv_msg: Cw20ReceiveMsg,
+)-> Result<Response, ContractError> {
+    let token_symbol = TOKENADDRESS.may_load(deps.storage, info.sender.as_ref())?
+        .ok_or(ContractError::WrongTokenContractError {})?;
+
+    match from_binary::<Cw20HookMsg>(&rcv_msg.msg) {
+        Ok(Cw20HookMsg::PlaceBid { nft_address, auction_id }) => {
+            let bidder = deps.api.addr_validate(&rcv_msg.sender)?;
+            let payment = AssetInfo::Token { contract_addr: info.sender.to_string() };
+            execute_place_bid(deps,env,bidder,nft_address,auction_id,payment,token_symbol,rcv_msg.amount)
+        }
+        Ok(Cw20HookMsg::MakeOffer { nft_address, token_id, expires_at }) => {
+            let buyer = deps.api.addr_validate(&rcv_msg.sender)?;
+            let price = Asset { denom: token_symbol, amount: rcv_msg.amount };
+            let payment = AssetInfo::Token { contract_addr: info.sender.to_string() };
+            execute_make_offer(deps,env,buyer,nft_address,token_id,expires_at,price,payment)
+        }
+        Ok(Cw20HookMsg::PlaceCollectionBid { nft_address, price, quantity, traits }) => {
+            let buyer = deps.api.addr_validate(&rcv_msg.sender)?;
+            let payment = Asset { denom: token_symbol, amount: rcv_msg.amount };
+            let payment_asset = AssetInfo::Token { contract_addr: info.sender.to_string() };
+            execute_place_collection_bid(deps,env,buyer,nft_address,price,quantity,traits.unwrap_or_default(),payment,payment_asset)
+        }
+        Ok(Cw20HookMsg::BuyBundle { bundle_id }) => {
+            let buyer = deps.api.addr_validate(&rcv_msg.sender)?;
+            let payment = Asset { denom: token_symbol, amount: rcv_msg.amount };
+            let payment_asset = AssetInfo::Token { contract_addr: info.sender.to_string() };
+            execute_buy_bundle(deps,env,buyer,bundle_id,payment,payment_asset)
+        }
+        Ok(Cw20HookMsg::BuyMany { items, max_total }) => {
+            let buyer = deps.api.addr_validate(&rcv_msg.sender)?;
+            let payment = Asset { denom: token_symbol, amount: rcv_msg.amount };
+            let payment_asset = AssetInfo::Token { contract_addr: info.sender.to_string() };
+            execute_buy_many(deps,env,buyer,items,max_total,payment,payment_asset)
+        }
+        Ok(Cw20HookMsg::BuyMint { nft_address, template_id }) => {
+            let buyer = deps.api.addr_validate(&rcv_msg.sender)?;
+            let payment = Asset { denom: token_symbol, amount: rcv_msg.amount };
+            execute_buy_mint(deps,env,buyer,nft_address,template_id,payment)
+        }
+        Ok(Cw20HookMsg::LaunchMint { nft_address, quantity, proof }) => {
+            let minter = deps.api.addr_validate(&rcv_msg.sender)?;
+            let payment = Asset { denom: token_symbol, amount: rcv_msg.amount };
+            execute_launch_mint(deps,env,minter,nft_address,quantity,proof,payment)
+        }
+        Ok(Cw20HookMsg::FundSwap { swap_id }) => {
+            let payment = PaymentAsset {
+                info: AssetInfo::Token { contract_addr: info.sender.to_string() },
+                amount: rcv_msg.amount
+            };
+            execute_fund_swap(deps,env,rcv_msg.sender,swap_id,payment)
+        }
+        // Anything else is a purchase of a fixed-price offering
+        Err(_) => execute_buy_with_token(deps,env,info,token_symbol,rcv_msg),
     }
-    let token_symbol = token_symbol.unwrap();
+}
 
+fn execute_buy_with_token(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    token_symbol: String,
+    rcv_msg: Cw20ReceiveMsg,
+)-> Result<Response, ContractError> {
     let msg:BuyNft = from_binary(&rcv_msg.msg)?;
     deps.api.addr_validate(&msg.nft_address)?;
 
-    let collection_info = COLLECTIONINFO.may_load(deps.storage, &msg.nft_address)?;
-    if collection_info == None{
-        return Err(ContractError::WrongNFTContractError {  })
+    let collection_info = COLLECTIONINFO.may_load(deps.storage, &msg.nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+
+    let off = OFFERINGS.load(deps.storage, (&msg.nft_address,msg.offering_id))?;
+    let price = offering_price(deps.as_ref(), &env, &off, &token_symbol)?
+        .ok_or_else(|| ContractError::UnexpectedDenom { denom: token_symbol.clone() })?;
+    assert_offering_matches(&off, price, &msg.token_id, msg.max_price)?;
+    assert_offering_active(&off, &env)?;
+    let buyer = deps.api.addr_validate(&rcv_msg.sender)?;
+    assert_buyer_allowed(&off, &buyer, msg.proof.as_deref())?;
+    let referrer = validate_referrer(deps.as_ref(), msg.referrer, &buyer, &off)?;
+    assert_listing_approved(deps.as_ref(), &env, &msg.nft_address, &off)?;
+
+    let excess = excess_payment(&off, price, rcv_msg.amount)?;
+
+    remove_offering(deps.storage, &msg.nft_address, msg.offering_id)?;
+
+    let payment = AssetInfo::Token { contract_addr: info.sender.to_string() };
+    let mut messages = settle_referred_sale(deps.storage, deps.api, deps.querier, &collection_info, &payment,
+        SaleInfo {
+            from:off.seller,
+            to: buyer.to_string(),
+            denom: token_symbol,
+            amount: price,
+            time: env.block.time.seconds(),
+            nft_address:msg.nft_address,
+            token_id:off.token_id,
+            royalty_source:None
+        }, referrer)?;
+    if !excess.is_zero() {
+        messages.push(PaymentAsset { info: payment, amount: excess }.into_msg(buyer)?);
     }
 
-    let off = OFFERINGS.load(deps.storage, (&msg.nft_address,&msg.offering_id))?;
+    Ok(Response::new().add_messages(messages))
+}
 
-    
-    if off.list_price.denom != token_symbol{
-        return Err(ContractError::NotEnoughFunds  { })
-    }
+#[allow(clippy::too_many_arguments)]
+fn execute_buy_nft(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    offering_id: u64,
+    nft_address:String,
+    token_id:Option<String>,
+    max_price:Option<Uint128>,
+    proof:Option<Vec<HexBinary>>,
+    referrer:Option<String>
+) -> Result<Response, ContractError> {
 
-    if off.list_price.amount != rcv_msg.amount{
-        return Err(ContractError::NotEnoughFunds  { })
+    let collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+    let off = OFFERINGS.load(deps.storage, (&nft_address, offering_id))?;
+    let coin = single_native_coin(&info)?;
+    let (denom, amount) = (coin.denom.clone(), coin.amount);
+    let price = offering_price(deps.as_ref(), &env, &off, &denom)?
+        .ok_or_else(|| ContractError::UnexpectedDenom { denom: denom.clone() })?;
+    assert_offering_matches(&off, price, &token_id, max_price)?;
+    assert_offering_active(&off, &env)?;
+    assert_buyer_allowed(&off, &info.sender, proof.as_deref())?;
+    let referrer = validate_referrer(deps.as_ref(), referrer, &info.sender, &off)?;
+    assert_listing_approved(deps.as_ref(), &env, &nft_address, &off)?;
+
+    let excess = excess_payment(&off, price, amount)?;
+
+    remove_offering(deps.storage, &nft_address, offering_id)?;
+
+    let payment = AssetInfo::NativeToken { denom: denom.clone() };
+    let mut messages = settle_referred_sale(deps.storage, deps.api, deps.querier, &collection_info, &payment,
+        SaleInfo {
+            from:off.seller,
+            to: info.sender.to_string(),
+            denom,
+            amount: price,
+            time: env.block.time.seconds(),
+            nft_address,
+            token_id:off.token_id,
+            royalty_source:None
+        }, referrer)?;
+    if !excess.is_zero() {
+        messages.push(PaymentAsset { info: payment, amount: excess }.into_msg(info.sender)?);
     }
 
-    let tvl = TVL.may_load(deps.storage, (&msg.nft_address,&off.list_price.denom))?;
-    let crr_tvl:Uint128;
-    if tvl == None {
-        crr_tvl = off.list_price.amount;
+    Ok(Response::new().add_messages(messages))
+}
+
+fn execute_buy_many_native(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    items: Vec<(String,u64)>,
+    max_total: Uint128
+) -> Result<Response, ContractError> {
+    let coin = single_native_coin(&info)?;
+    let payment = Asset { denom: coin.denom.clone(), amount: coin.amount };
+    let payment_asset = AssetInfo::NativeToken { denom: coin.denom.clone() };
+    execute_buy_many(deps,env,info.sender,items,max_total,payment,payment_asset)
+}
+
+/// Buys every item in one currency, or nothing. Payouts are merged per
+/// recipient and whatever is left of `payment` goes back to the buyer in
+/// `payment_asset`, the coin or cw20 contract it was sent in.
+fn execute_buy_many(
+    deps: DepsMut,
+    env:Env,
+    buyer: Addr,
+    items: Vec<(String,u64)>,
+    max_total: Uint128,
+    payment: Asset,
+    payment_asset: AssetInfo
+) -> Result<Response, ContractError> {
+    if items.is_empty() {
+        return Err(ContractError::InvalidQuantity {});
     }
-    else {
-        crr_tvl = tvl.unwrap()+off.list_price.amount;
+
+    let mut messages:Vec<CosmosMsg> = vec![];
+    let mut payouts:Vec<(String, Uint128)> = vec![];
+    let mut total = Uint128::zero();
+    for (nft_address, offering_id) in items.iter() {
+        let collection_info = COLLECTIONINFO.may_load(deps.storage, nft_address)?
+            .ok_or(ContractError::WrongNFTContractError {})?;
+        let off = OFFERINGS.load(deps.storage, (nft_address, *offering_id))?;
+        let price = offering_price(deps.as_ref(), &env, &off, &payment.denom)?
+            .ok_or_else(|| ContractError::UnexpectedDenom { denom: payment.denom.clone() })?;
+        assert_offering_matches(&off, price, &None, Some(max_total))?;
+        assert_offering_active(&off, &env)?;
+        assert_buyer_allowed(&off, &buyer, None)?;
+        assert_listing_approved(deps.as_ref(), &env, nft_address, &off)?;
+
+        remove_offering(deps.storage, nft_address, *offering_id)?;
+        total += price;
+
+        messages.push(transfer_nft_msg(NftRef { nft_address: nft_address.clone(), token_id: off.token_id.clone() }, &buyer)?);
+        let fee = protocol_fee(deps.storage, &collection_info, price)?;
+        accrue_fee(deps.storage, &payment.denom, fee)?;
+        let (shares, royalty_source) = sale_shares(deps.storage, deps.api, deps.querier, &collection_info, &off.seller, &off.token_id, price, fee)?;
+        if collection_info.pull_payments {
+            pay_shares(deps.storage, true, &payment_asset, &payment.denom, shares)?;
+        } else {
+            for (recipient, amount) in shares {
+                match payouts.iter_mut().find(|(address, _)| *address == recipient) {
+                    Some((_, payout)) => *payout += amount,
+                    None => payouts.push((recipient, amount)),
+                }
+            }
+        }
+        record_sale(deps.storage, &SaleInfo {
+            from: off.seller,
+            to: buyer.to_string(),
+            denom: payment.denom.clone(),
+            amount: price,
+            time: env.block.time.seconds(),
+            nft_address: nft_address.clone(),
+            token_id: off.token_id,
+            royalty_source: Some(royalty_source)
+        }, payment_asset.is_native_token())?;
     }
 
-    TVL.save(deps.storage,( &msg.nft_address,&off.list_price.denom), &crr_tvl)?;
-  
-    let members = MEMBERS.load(deps.storage,&msg.nft_address)?;
-    let collection_info = COLLECTIONINFO.may_load(deps.storage, &msg.nft_address)?;
+    if total > max_total {
+        return Err(ContractError::OfferingMismatch {});
+    }
+    if total > payment.amount {
+        return Err(ContractError::NotEnoughFunds {});
+    }
 
-    if collection_info == None{
-        return Err(ContractError::WrongNFTContractError {  })
+    for (recipient, amount) in payouts {
+        if !amount.is_zero() {
+            messages.push(PaymentAsset { info: payment_asset.clone(), amount }.into_msg(Addr::unchecked(recipient))?);
+        }
     }
+    let refund = payment.amount - total;
+    if !refund.is_zero() {
+        messages.push(PaymentAsset { info: payment_asset, amount: refund }.into_msg(buyer)?);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "buy_many")
+        .add_attribute("count", items.len().to_string())
+        .add_attribute("total", total.to_string())
+        .add_messages(messages))
+}
 
-    let collection_info = collection_info.unwrap();
+/// Books a completed sale and returns the NFT transfer to the buyer followed
+/// by the seller proceeds and the royalty split over the collection `MEMBERS`.
+fn settle_sale(
+    storage: &mut dyn Storage,
+    api: &dyn Api,
+    querier: QuerierWrapper,
+    collection_info: &CollectionInfo,
+    payment: &AssetInfo,
+    sale: SaleInfo
+) -> StdResult<Vec<CosmosMsg>> {
+    settle_referred_sale(storage, api, querier, collection_info, payment, sale, None)
+}
 
-    if collection_info.offering_id == 1{    
-          OFFERINGS.remove( deps.storage, (&msg.nft_address,&msg.offering_id));
-          COLLECTIONINFO.update(deps.storage, &msg.nft_address,
-             |collection_info|->StdResult<_>{
-                    let mut collection_info = collection_info.unwrap();
-                    collection_info.offering_id = 0;
-                    Ok(collection_info)
-             })?;
+/// `settle_sale` that also pays the referrer its cut, out of the seller's
+/// share, and adds the sale to the referrer's volume.
+fn settle_referred_sale(
+    storage: &mut dyn Storage,
+    api: &dyn Api,
+    querier: QuerierWrapper,
+    collection_info: &CollectionInfo,
+    payment: &AssetInfo,
+    mut sale: SaleInfo,
+    referrer: Option<Addr>
+) -> StdResult<Vec<CosmosMsg>> {
+    let mut messages:Vec<CosmosMsg> = vec![CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: sale.nft_address.clone(),
+        funds: vec![],
+        msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+            recipient: sale.to.clone(),
+            token_id: sale.token_id.clone(),
+        })?,
+    })];
+    let fee = protocol_fee(storage, collection_info, sale.amount)?;
+    let referral = match &referrer {
+        Some(referrer) => {
+            let volume = REFERRAL_VOLUME.may_load(storage, (referrer.as_str(), &sale.denom))?.unwrap_or_default() + sale.amount;
+            REFERRAL_VOLUME.save(storage, (referrer.as_str(), &sale.denom), &volume)?;
+            referral_fee(storage, collection_info, sale.amount)?
+        }
+        None => Uint128::zero(),
+    };
+    let (mut shares, royalty_source) = sale_shares(storage, api, querier, collection_info, &sale.from, &sale.token_id, sale.amount, fee + referral)?;
+    sale.royalty_source = Some(royalty_source);
+    if let Some(referrer) = referrer {
+        if !referral.is_zero() {
+            shares.push((referrer.to_string(), referral));
+        }
     }
+    messages.extend(pay_shares(storage, collection_info.pull_payments, payment, &sale.denom, shares)?);
+    accrue_fee(storage, &sale.denom, fee)?;
+    record_sale(storage, &sale, payment.is_native_token())?;
+    Ok(messages)
+}
 
-    else{
-        let crr_offering_id = collection_info.offering_id;
-        let offering = OFFERINGS.may_load(deps.storage, (&msg.nft_address,&crr_offering_id.to_string()))?;
-        
-        if offering!=None{
-            OFFERINGS.save(deps.storage, (&msg.nft_address,&msg.offering_id.to_string()), &offering.unwrap())?;
-           
-            COLLECTIONINFO.update(deps.storage, &msg.nft_address,
-                |collection_info|->StdResult<_>{
-                        let mut collection_info = collection_info.unwrap();
-                        collection_info.offering_id =collection_info.offering_id-1;
-                        Ok(collection_info)
-                })?;
-          OFFERINGS.remove( deps.storage, (&msg.nft_address,&crr_offering_id.to_string()));
-         }
+/// Sends each share, or credits it to the claim ledger under `denom` when
+/// the collection uses pull payments.
+fn pay_shares(
+    storage: &mut dyn Storage,
+    pull_payments: bool,
+    payment: &AssetInfo,
+    denom: &str,
+    shares: Vec<(String, Uint128)>
+) -> StdResult<Vec<CosmosMsg>> {
+    if pull_payments {
+        for (recipient, amount) in shares {
+            credit_payment(storage, &recipient, denom, amount)?;
+        }
+        return Ok(vec![]);
+    }
+    shares
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(recipient, amount)| PaymentAsset {
+            info: payment.clone(),
+            amount
+        }.into_msg(Addr::unchecked(recipient)))
+        .collect()
+}
 
-         
+fn credit_payment(storage: &mut dyn Storage, recipient: &str, denom: &str, amount: Uint128) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
     }
+    let pending = PENDING_PAYMENTS.may_load(storage, (recipient, denom))?.unwrap_or_default() + amount;
+    PENDING_PAYMENTS.save(storage, (recipient, denom), &pending)
+}
 
-    let mut messages:Vec<CosmosMsg> = vec![];
-    for user in members{
-        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: info.sender.to_string(),
-                funds: vec![],
-                msg: to_binary(&Cw20ExecuteMsg::Transfer { 
-                    recipient: user.address.clone(), 
-                    amount: rcv_msg.amount*collection_info.royalty_portion*user.portion })?,
-        }))
+/// Splits a sale of `amount` between the seller and the royalty recipients,
+/// reporting where the royalty came from. `deductions` (protocol fee,
+/// referral cut) come out of the seller's share.
+#[allow(clippy::too_many_arguments)]
+fn sale_shares(
+    storage: &dyn Storage,
+    api: &dyn Api,
+    querier: QuerierWrapper,
+    collection_info: &CollectionInfo,
+    seller: &str,
+    token_id: &str,
+    amount: Uint128,
+    deductions: Uint128
+) -> StdResult<(Vec<(String, Uint128)>, RoyaltySource)> {
+    if collection_info.cw2981_royalties {
+        if let Some(royalty) = cw2981_royalty(api, querier, &collection_info.nft_address, token_id, amount)? {
+            // The fee and referral come first, a royalty that leaves no room
+            // for them is cut down to what remains.
+            let proceeds = amount.checked_sub(deductions)?;
+            let royalty_amount = royalty.royalty_amount.min(proceeds);
+            let mut shares = vec![(seller.to_string(), proceeds - royalty_amount)];
+            if !royalty_amount.is_zero() {
+                shares.push((royalty.address, royalty_amount));
+            }
+            return Ok((shares, RoyaltySource::Cw2981));
+        }
     }
 
-    let price_info = PRICEINFO.may_load(deps.storage,&msg.nft_address)?;
-    if price_info == None{
-        PRICEINFO.save(deps.storage,&msg.nft_address,&PriceInfo {
-            total_juno:Uint128::new(0) ,
-            total_hope: rcv_msg.amount })?;
-       }
-    else{
-        PRICEINFO.update(deps.storage,&msg.nft_address,
-        |price_info|->StdResult<_>{
-            let mut price_info = price_info.unwrap();
-            price_info.total_hope = price_info.total_hope + rcv_msg.amount;
-            Ok(price_info)
-        })?;}
-    let sale_id = collection_info.sale_id+1;
-
-    SALEHISTORY.save(deps.storage, (&msg.nft_address,&sale_id.to_string()),&SaleInfo { 
-        from:off.seller.clone(),
-        to: rcv_msg.sender.to_string(), 
-        denom: off.list_price.denom,
-        amount: rcv_msg.amount, 
-        time: env.block.time.seconds(),
-        nft_address:msg.nft_address.clone(),
-        token_id:off.token_id.clone()
-    })?;
+    let members = MEMBERS.load(storage, &collection_info.nft_address)?;
 
-    COLLECTIONINFO.update(deps.storage, &msg.nft_address, 
-        |collection_info|->StdResult<_>{
-            let mut collection_info = collection_info.unwrap();
-            collection_info.sale_id = sale_id;
-            Ok(collection_info)
-        }
-    )?;
-    
+    // As with cw2981, the royalty is cut down to what the deductions leave.
+    // The seller takes the rest, so the shares always add up to `amount`.
+    let proceeds = amount.checked_sub(deductions)?;
+    let royalty = (amount * collection_info.royalty_portion).min(proceeds);
+    let mut shares = vec![(seller.to_string(), proceeds - royalty)];
+    shares.extend(split_by_portion(royalty, members));
+    Ok((shares, RoyaltySource::Members))
+}
 
-    Ok(Response::new()
-        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: msg.nft_address.to_string(),
-                funds: vec![],
-                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                    recipient: deps.api.addr_validate(&rcv_msg.sender)?.to_string(),
-                    token_id: off.token_id.clone(),
-            })?,
-        }))
-        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: info.sender.to_string(),
-                funds: vec![],
-                msg: to_binary(&Cw20ExecuteMsg::Transfer { 
-                    recipient: off.seller, 
-                    amount: rcv_msg.amount*(Decimal::one()-collection_info.royalty_portion) })?,
-        }))
-        .add_messages(messages)
-)
+/// Splits `total` over `members` by portion. Truncation dust goes to the
+/// last member, so the shares always add up to `total`.
+fn split_by_portion(total: Uint128, members: Vec<UserInfo>) -> Vec<(String, Uint128)> {
+    let count = members.len();
+    let mut remaining = total;
+    members
+        .into_iter()
+        .enumerate()
+        .map(|(i, user)| {
+            let share = if i + 1 == count { remaining } else { (total * user.portion).min(remaining) };
+            remaining -= share;
+            (user.address, share)
+        })
+        .collect()
 }
 
-fn execute_buy_nft(
-    deps: DepsMut,
-    env:Env,
-    info: MessageInfo,
-    offering_id: String,
-    nft_address:String
-) -> Result<Response, ContractError> {
-  
-    let collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?;
-    if collection_info == None{
-        return Err(ContractError::WrongNFTContractError {  })
+/// The token's cw2981 royalty for a sale at `sale_price`. `None` when the
+/// contract does not support the query or asks for more than the price; a
+/// royalty address that does not validate fails the sale.
+fn cw2981_royalty(api: &dyn Api, querier: QuerierWrapper, nft_address: &str, token_id: &str, sale_price: Uint128) -> StdResult<Option<RoyaltiesInfoResponse>> {
+    let royalty: RoyaltiesInfoResponse = match querier.query_wasm_smart(nft_address, &Cw2981QueryMsg::Extension {
+        msg: Cw2981Query::RoyaltyInfo { token_id: token_id.to_string(), sale_price }
+    }) {
+        Ok(royalty) => royalty,
+        Err(_) => return Ok(None),
+    };
+    if royalty.royalty_amount.is_zero() {
+        return Ok(Some(royalty));
     }
-    let collection_info = collection_info.unwrap();
-    let off = OFFERINGS.load(deps.storage, (&nft_address, &offering_id))?;
-
-    let amount= info
-        .funds
-        .iter()
-        .find(|c| c.denom == off.list_price.denom)
-        .map(|c| Uint128::from(c.amount))
-        .unwrap_or_else(Uint128::zero);
-
-    if off.list_price.amount!=amount{
-        return Err(ContractError::NotEnoughFunds {  })
-    }
-    if collection_info.offering_id == 1{    
-          OFFERINGS.remove( deps.storage, (&nft_address,&offering_id));
-          COLLECTIONINFO.update(deps.storage, &nft_address,
-             |collection_info|->StdResult<_>{
-                    let mut collection_info = collection_info.unwrap();
-                    collection_info.offering_id = 0;
-                    Ok(collection_info)
-             })?;
-    }
-
-    else{
-        let crr_offering_id = collection_info.offering_id;
-        let offering = OFFERINGS.may_load(deps.storage, (&nft_address,&crr_offering_id.to_string()))?;
-        if offering!=None{
-            OFFERINGS.save(deps.storage, (&nft_address,&offering_id.to_string()), &offering.unwrap())?;
-           
-            COLLECTIONINFO.update(deps.storage, &nft_address,
-                |collection_info|->StdResult<_>{
-                        let mut collection_info = collection_info.unwrap();
-                        collection_info.offering_id =collection_info.offering_id-1;
-                        Ok(collection_info)
-                })?;
-        OFFERINGS.remove( deps.storage, (&nft_address,&crr_offering_id.to_string()));
-  
-         }
+    if royalty.royalty_amount > sale_price {
+        return Ok(None);
     }
-    
-    let members = MEMBERS.load(deps.storage,&nft_address)?;
-    
-    let mut messages:Vec<CosmosMsg> = vec![];
-    for user in members{
-        messages.push(CosmosMsg::Bank(BankMsg::Send {
-                to_address: user.address,
-                amount:vec![Coin{
-                    denom:off.clone().list_price.denom,
-                    amount:amount*collection_info.royalty_portion*user.portion
-                }]
-        }))
+    // A royalty we would pay out must go to a real account
+    api.addr_validate(&royalty.address)?;
+    Ok(Some(royalty))
+}
+
+/// Marketplace cut of a sale of `amount`, at the collection's override if
+/// it has one.
+fn protocol_fee(storage: &dyn Storage, collection_info: &CollectionInfo, amount: Uint128) -> StdResult<Uint128> {
+    let rate = match collection_info.protocol_fee {
+        Some(rate) => rate,
+        None => CONFIG.load(storage)?.protocol_fee,
+    };
+    Ok(amount * rate)
+}
+
+fn referral_fee(storage: &dyn Storage, collection_info: &CollectionInfo, amount: Uint128) -> StdResult<Uint128> {
+    let bps = match collection_info.referral_bps {
+        Some(bps) => bps,
+        None => CONFIG.load(storage)?.referral_bps,
+    };
+    Ok(amount.multiply_ratio(bps, 10_000u128))
+}
+
+/// The royalty, protocol fee and referral cut of a collection together may
+/// not take more than the sale price.
+fn assert_cuts_fit(state: &State, collection_info: &CollectionInfo) -> Result<(), ContractError> {
+    let fee = collection_info.protocol_fee.unwrap_or(state.protocol_fee);
+    let referral = Decimal::from_ratio(collection_info.referral_bps.unwrap_or(state.referral_bps), 10_000u128);
+    let total = collection_info.royalty_portion.checked_add(fee)
+        .and_then(|total| total.checked_add(referral));
+    if total.map_or(true, |total| total > Decimal::one()) {
+        return Err(ContractError::InvalidFee {});
     }
-   
-    let price_info = PRICEINFO.may_load(deps.storage,&nft_address)?;
-   if price_info == None{
-        PRICEINFO.save(deps.storage,&nft_address,&PriceInfo {
-            total_hope:Uint128::new(0) ,
-            total_juno: amount })?;
-       }
-    else{
-        PRICEINFO.update(deps.storage,&nft_address,
-        |price_info|->StdResult<_>{
-            let mut price_info = price_info.unwrap();
-            price_info.total_juno = price_info.total_juno + amount;
-            Ok(price_info)
-        })?;}
-    
-     let sale_id = collection_info.sale_id+1;
-
-    SALEHISTORY.save(deps.storage, (&nft_address,&sale_id.to_string()),&SaleInfo {
-         from:off.seller.clone(), 
-         to: info.sender.to_string(), 
-         denom: off.list_price.denom.clone(),
-         amount: amount, 
-         time: env.block.time.seconds(),
-         nft_address:nft_address.clone(),
-         token_id:off.token_id.clone()         
-        })?;
+    Ok(())
+}
+
+fn assert_all_cuts_fit(storage: &dyn Storage, state: &State) -> Result<(), ContractError> {
+    for item in COLLECTIONINFO.range(storage, None, None, Order::Ascending) {
+        let (_, collection_info) = item?;
+        assert_cuts_fit(state, &collection_info)?;
+    }
+    Ok(())
+}
+
+fn accrue_fee(storage: &mut dyn Storage, denom: &str, fee: Uint128) -> StdResult<()> {
+    if fee.is_zero() {
+        return Ok(());
+    }
+    let accrued = ACCRUED_FEES.may_load(storage, denom)?.unwrap_or_default() + fee;
+    ACCRUED_FEES.save(storage, denom, &accrued)
+}
+
+/// Adds a sale to the collection's TVL, trading totals and sale history.
+fn record_sale(storage: &mut dyn Storage, sale: &SaleInfo, native: bool) -> StdResult<()> {
+    let crr_tvl = TVL.may_load(storage, (&sale.nft_address,&sale.denom))?
+        .unwrap_or_default() + sale.amount;
+    TVL.save(storage,( &sale.nft_address,&sale.denom), &crr_tvl)?;
+
+    let mut price_info = PRICEINFO.may_load(storage,&sale.nft_address)?
+        .unwrap_or(PriceInfo {
+            total_juno:Uint128::new(0),
+            total_hope:Uint128::new(0) });
+    if native {
+        price_info.total_juno += sale.amount;
+    } else {
+        price_info.total_hope += sale.amount;
+    }
+    PRICEINFO.save(storage,&sale.nft_address,&price_info)?;
 
-    COLLECTIONINFO.update(deps.storage, &nft_address, 
+    let collection_info = COLLECTIONINFO.update(storage, &sale.nft_address,
         |collection_info|->StdResult<_>{
-            let mut collection_info = collection_info.unwrap();
-            collection_info.sale_id = sale_id;
+            let mut collection_info = collection_info
+                .ok_or_else(|| StdError::not_found("CollectionInfo"))?;
+            collection_info.sale_id += 1;
             Ok(collection_info)
         }
     )?;
+    SALEHISTORY.save(storage, (&sale.nft_address,&collection_info.sale_id.to_string()), sale)?;
+    Ok(())
+}
 
-    
-    let tvl = TVL.may_load(deps.storage, (&nft_address,&off.list_price.denom))?;
-    let crr_tvl:Uint128;
-    if tvl == None {
-        crr_tvl = off.list_price.amount;
+/// Resolves a listing denom to the asset that moves the funds: a registered
+/// cw20 symbol maps to its token contract, anything else is a native denom.
+fn payment_info(storage: &dyn Storage, denom: &str) -> StdResult<AssetInfo> {
+    let token = TOKENADDRESS
+        .range(storage, None, None, Order::Ascending)
+        .find(|item| item.as_ref().map_or(true, |(_, symbol)| symbol == denom))
+        .transpose()?;
+    Ok(match token {
+        Some((contract_addr, _)) => AssetInfo::Token { contract_addr },
+        None => AssetInfo::NativeToken { denom: denom.to_string() },
+    })
+}
+
+/// Guards a purchase against the offering having changed since the buyer looked at it.
+fn assert_offering_matches(
+    off: &Offering,
+    price: Uint128,
+    token_id: &Option<String>,
+    max_price: Option<Uint128>
+) -> Result<(), ContractError> {
+    if off.peg_unit.is_some() && max_price.is_none() {
+        return Err(ContractError::SlippageBoundRequired {});
     }
-    else {
-        crr_tvl = tvl.unwrap()+off.list_price.amount;
+    if let Some(token_id) = token_id {
+        if *token_id != off.token_id {
+            return Err(ContractError::OfferingMismatch {});
+        }
     }
-
-    TVL.save(deps.storage,( &nft_address,&off.list_price.denom), &crr_tvl)?;
-
-  
-    Ok(Response::new()
-        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: nft_address.to_string(),
-                funds: vec![],
-                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                    recipient: info.sender.to_string(),
-                    token_id: off.token_id.clone(),
-            })?,
-        }))
-        .add_message(CosmosMsg::Bank(BankMsg::Send {
-                to_address: off.seller,
-                amount:vec![Coin{
-                    denom:off.list_price.denom,
-                    amount:amount*(Decimal::one()-collection_info.royalty_portion)
-                }]
-        }))
-        .add_messages(messages)
-)
+    if let Some(max_price) = max_price {
+        if price > max_price {
+            return Err(ContractError::OfferingMismatch {});
+        }
+    }
+    Ok(())
 }
 
-fn execute_withdraw(
-    deps: DepsMut,
-    _env:Env,
-    info: MessageInfo,
-    offering_id: String,
-    nft_address:String
-) -> Result<Response, ContractError> {
-    let off = OFFERINGS.load(deps.storage,(&nft_address,&offering_id))?;
-   // let state = CONFIG.load(deps.storage)?;
-
-    let collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?;
-    if collection_info == None{
-        return Err(ContractError::WrongNFTContractError {  })
+/// Price of the offering at the current block time. Fixed-price offerings
+/// always cost `list_price`.
+fn current_price(off: &Offering, env: &Env) -> Uint128 {
+    let decay = match &off.price_decay {
+        Some(decay) => decay,
+        None => return off.list_price.amount,
+    };
+    let now = env.block.time.seconds();
+    if now <= decay.start_time {
+        return off.list_price.amount;
     }
-   let collection_info = collection_info.unwrap();
-
-   if collection_info.offering_id == 1{    
-          OFFERINGS.remove( deps.storage, (&nft_address,&offering_id));
-          COLLECTIONINFO.update(deps.storage, &nft_address,
-             |collection_info|->StdResult<_>{
-                    let mut collection_info = collection_info.unwrap();
-                    collection_info.offering_id = 0;
-                    Ok(collection_info)
-             })?;
-    }
-
-    else{
-        let crr_offering_id = collection_info.offering_id;
-        let offering = OFFERINGS.may_load(deps.storage, (&nft_address,&crr_offering_id.to_string()))?;
-        if offering!=None{
-            OFFERINGS.save(deps.storage, (&nft_address,&offering_id.to_string()), &offering.unwrap())?;
-           
-            COLLECTIONINFO.update(deps.storage, &nft_address,
-                |collection_info|->StdResult<_>{
-                        let mut collection_info = collection_info.unwrap();
-                        collection_info.offering_id =collection_info.offering_id-1;
-                        Ok(collection_info)
-                })?;
-        OFFERINGS.remove( deps.storage, (&nft_address,&crr_offering_id.to_string()));
-  
-         }
+    if now >= decay.end_time {
+        return decay.end_price;
     }
 
-    if off.seller == info.sender.to_string(){
-
-        Ok(Response::new()
-            .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: nft_address.to_string(),
-                funds: vec![],
-                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                    recipient: deps.api.addr_validate(&off.seller)?.to_string(),
-                    token_id: off.token_id.clone(),
-            })?,
-        }))
-    )
+    let mut elapsed = now - decay.start_time;
+    if let Some(step_seconds) = decay.step_seconds {
+        elapsed -= elapsed % step_seconds;
     }
-    else {
-        return Err(ContractError::Unauthorized {});
+    let drop = (off.list_price.amount - decay.end_price)
+        .multiply_ratio(elapsed, decay.end_time - decay.start_time);
+    off.list_price.amount - drop
+}
+
+/// Price of the offering in `denom`, if the seller accepts that currency.
+/// A Dutch auction only declines in the `list_price` currency.
+fn price_in(off: &Offering, env: &Env, denom: &str) -> Option<Uint128> {
+    if off.list_price.denom == denom {
+        return Some(current_price(off, env));
     }
-    
+    off.alt_prices.iter().find(|price| price.denom == denom).map(|price| price.amount)
 }
 
+/// Like `price_in`, with pegged offerings converted from their reference unit
+/// at the oracle rate.
+fn offering_price(deps: Deps, env: &Env, off: &Offering, denom: &str) -> StdResult<Option<Uint128>> {
+    let amount = match price_in(off, env, denom) {
+        Some(amount) => amount,
+        None => return Ok(None),
+    };
+    match &off.peg_unit {
+        Some(peg_unit) => Ok(Some(amount * oracle_rate(deps, peg_unit, denom)?)),
+        None => Ok(Some(amount)),
+    }
+}
 
-fn execute_add_collection(
-    deps: DepsMut,
-    _env:Env,
-    info: MessageInfo,
-    royalty_potion: Decimal,
-    members: Vec<UserInfo>,
-    nft_address:String,
-    offering_id:u64,
-    sale_id:u64
-)->Result<Response,ContractError>{
+fn oracle_rate(deps: Deps, base: &str, quote: &str) -> StdResult<Decimal> {
+    let oracle = CONFIG.load(deps.storage)?.oracle
+        .ok_or_else(|| StdError::generic_err("Oracle is not set"))?;
+    let res: OraclePriceResponse = deps.querier.query_wasm_smart(oracle, &OracleQueryMsg::Price {
+        base: base.to_string(),
+        quote: quote.to_string()
+    })?;
+    if res.rate.is_zero() {
+        return Err(StdError::generic_err("Oracle returned a zero rate"));
+    }
+    Ok(res.rate)
+}
 
-    let state = CONFIG.load(deps.storage)?;
+/// Fixed-price offerings must be paid exactly. A Dutch auction or a pegged
+/// offering may be paid above its live price, since the price can move before
+/// the transaction lands; the difference is returned to the buyer.
+fn excess_payment(off: &Offering, price: Uint128, paid: Uint128) -> Result<Uint128, ContractError> {
+    if off.price_decay.is_none() && off.peg_unit.is_none() {
+        assert_exact_payment(paid, price)?;
+    } else if paid < price {
+        return Err(ContractError::NotEnoughFunds {});
+    }
+    Ok(paid - price)
+}
 
-    deps.api.addr_validate(&nft_address)?;
+/// Referrers earn from other people's purchases only.
+fn validate_referrer(deps: Deps, referrer: Option<String>, buyer: &Addr, off: &Offering) -> Result<Option<Addr>, ContractError> {
+    let referrer = match referrer {
+        Some(referrer) => deps.api.addr_validate(&referrer)?,
+        None => return Ok(None),
+    };
+    if referrer == buyer || referrer == off.seller {
+        return Err(ContractError::InvalidReferrer {});
+    }
+    Ok(Some(referrer))
+}
 
-    if info.sender.to_string() != state.owner{
-        return Err(ContractError::Unauthorized {});
+/// Private listings can only be bought by the buyer they were reserved for,
+/// or by one proven to be in their Merkle root.
+fn assert_buyer_allowed(off: &Offering, buyer: &Addr, proof: Option<&[HexBinary]>) -> Result<(), ContractError> {
+    if !off.is_reserved() || off.reserved_for.as_ref().is_some_and(|reserved_for| reserved_for == buyer) {
+        return Ok(());
     }
-    
-    let mut sum_portion = Decimal::zero();
+    match (&off.reserved_root, proof) {
+        (Some(root), Some(proof)) if verify_proof(root, buyer.as_str(), proof) => Ok(()),
+        _ => Err(ContractError::ReservedOffering {}),
+    }
+}
 
-    for item in members.clone() {
-        sum_portion = sum_portion + item.portion;
-        deps.api.addr_validate(&item.address)?;
+fn assert_listing_approved(deps: Deps, env: &Env, nft_address: &str, off: &Offering) -> Result<(), ContractError> {
+    if off.non_custodial && !is_listing_approved(deps, env, nft_address, &off.token_id, &off.seller) {
+        return Err(ContractError::StaleListing {});
     }
+    Ok(())
+}
 
-    if sum_portion != Decimal::one(){
-        return Err(ContractError::WrongPortionError { })
+/// Whether `seller` still owns the token and has it approved to the
+/// marketplace. A failed `OwnerOf` query means the token is gone.
+fn is_listing_approved(deps: Deps, env: &Env, nft_address: &str, token_id: &str, seller: &str) -> bool {
+    let owner: StdResult<OwnerOfResponse> = deps.querier.query_wasm_smart(nft_address, &Cw721QueryMsg::OwnerOf {
+        token_id: token_id.to_string(),
+        include_expired: Some(false)
+    });
+    match owner {
+        Ok(owner) => owner.owner == seller
+            && owner.approvals.iter().any(|approval| approval.spender == env.contract.address),
+        Err(_) => false,
     }
+}
 
-    MEMBERS.save(deps.storage,&nft_address, &members)?;
-    COLLECTIONINFO.save(deps.storage,&nft_address,&CollectionInfo{
-        nft_address:nft_address.clone(),
-        offering_id:offering_id,
-        sale_id:sale_id,
-        royalty_portion:royalty_potion
-    })?;
-    Ok(Response::default())
+fn assert_offering_active(off: &Offering, env: &Env) -> Result<(), ContractError> {
+    if env.block.time.seconds() < off.starts_at.unwrap_or_default() {
+        return Err(ContractError::ListingNotStarted {});
+    }
+    if is_expired(off, env) {
+        return Err(ContractError::ListingExpired {});
+    }
+    Ok(())
 }
 
+fn is_expired(off: &Offering, env: &Env) -> bool {
+    match off.expires_at {
+        Some(expires_at) => env.block.time.seconds() >= expires_at,
+        None => false,
+    }
+}
 
-fn execute_update_collection(
+fn execute_withdraw(
     deps: DepsMut,
     _env:Env,
     info: MessageInfo,
-    royalty_potion: Decimal,
-    members: Vec<UserInfo>,
+    offering_id: u64,
     nft_address:String
-)->Result<Response,ContractError>{
-
-    let state = CONFIG.load(deps.storage)?;
-
-    deps.api.addr_validate(&nft_address)?;
+) -> Result<Response, ContractError> {
+    let off = OFFERINGS.load(deps.storage,(&nft_address,offering_id))?;
 
-    if info.sender.to_string() != state.owner{
+    if off.seller != info.sender {
         return Err(ContractError::Unauthorized {});
     }
 
-    let collection_info = COLLECTIONINFO.may_load(deps.storage,&nft_address)?;
-    if collection_info == None{
-        return Err(ContractError::WrongCollection {  })
+    if !COLLECTIONINFO.has(deps.storage, &nft_address){
+        return Err(ContractError::WrongNFTContractError {  })
     }
-    let collection_info = collection_info.unwrap();
 
-    let mut sum_portion = Decimal::zero();
+    remove_offering(deps.storage, &nft_address, offering_id)?;
 
-    for item in members.clone() {
-        sum_portion = sum_portion + item.portion;
-        deps.api.addr_validate(&item.address)?;
+    if off.non_custodial {
+        return Ok(Response::new());
     }
 
-    if sum_portion != Decimal::one(){
-        return Err(ContractError::WrongPortionError { })
-    }
-
-    MEMBERS.save(deps.storage,&nft_address, &members)?;
-    COLLECTIONINFO.save(deps.storage,&nft_address,&CollectionInfo{
-        nft_address:nft_address.clone(),
-        offering_id:collection_info.offering_id,
-        royalty_portion:royalty_potion,
-        sale_id:collection_info.sale_id
-    })?;
-    Ok(Response::default())
+    Ok(Response::new()
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: nft_address.to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: deps.api.addr_validate(&off.seller)?.to_string(),
+                token_id: off.token_id.clone(),
+        })?,
+    }))
+    )
 }
 
-
-fn execute_token_address(
+fn execute_update_price(
     deps: DepsMut,
     _env:Env,
     info: MessageInfo,
-    symbol:String,
-    address: String,
+    nft_address:String,
+    offering_id: u64,
+    list_price: Asset
 ) -> Result<Response, ContractError> {
-    let  state = CONFIG.load(deps.storage)?;
-    deps.api.addr_validate(&address)?;
+    let mut off = OFFERINGS.load(deps.storage,(&nft_address,offering_id))?;
 
-     if info.sender.to_string() != state.owner{
+    if off.seller != info.sender {
         return Err(ContractError::Unauthorized {});
     }
-    
-    TOKENADDRESS.save(deps.storage,&address,&symbol)?;
 
-    CONFIG.save(deps.storage, &state)?;
-    Ok(Response::default())
-}
+    validate_list_price(&list_price)?;
+    validate_price_decay(&list_price, &off.price_decay)?;
+    validate_alt_prices(&list_price, &off.alt_prices)?;
 
+    let old_price = off.list_price;
+    off.list_price = list_price;
+    OFFERINGS.save(deps.storage, (&nft_address,offering_id), &off)?;
 
-fn execute_fix_nft(
-    deps: DepsMut,
-    _env:Env,
-    info: MessageInfo,
-    address: String,
-    token_id:String
-) -> Result<Response, ContractError> {
-    let state = CONFIG.load(deps.storage)?;
-    deps.api.addr_validate(&address)?;
-    if state.owner != info.sender.to_string() {
-        return Err(ContractError::Unauthorized {});
+    Ok(Response::new()
+        .add_attribute("action", "update_price")
+        .add_attribute("nft_address", nft_address)
+        .add_attribute("offering_id", offering_id.to_string())
+        .add_attribute("old_price", old_price.to_string())
+        .add_attribute("new_price", off.list_price.to_string()))
+}
+
+fn validate_list_price(list_price: &Asset) -> Result<(), ContractError> {
+    if list_price.amount.is_zero() || list_price.denom.is_empty() {
+        return Err(ContractError::InvalidPrice {});
     }
+    Ok(())
+}
 
-    Ok(Response::new()
-        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: address,
-                funds: vec![],
-                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                    recipient: info.sender.to_string(),
-                    token_id: token_id.clone(),
-            })?,
-        })))
+/// Extra currencies must be valid prices, each in a denom of its own.
+fn validate_alt_prices(list_price: &Asset, alt_prices: &[Asset]) -> Result<(), ContractError> {
+    for (i, price) in alt_prices.iter().enumerate() {
+        validate_list_price(price)?;
+        if price.denom == list_price.denom || alt_prices[..i].iter().any(|other| other.denom == price.denom) {
+            return Err(ContractError::InvalidPrice {});
+        }
+    }
+    Ok(())
 }
 
+fn validate_price_decay(list_price: &Asset, price_decay: &Option<PriceDecay>) -> Result<(), ContractError> {
+    if let Some(decay) = price_decay {
+        if decay.end_price.is_zero() || decay.end_price >= list_price.amount {
+            return Err(ContractError::InvalidPrice {});
+        }
+        if decay.end_time <= decay.start_time || decay.step_seconds == Some(0) {
+            return Err(ContractError::InvalidListingTime {});
+        }
+    }
+    Ok(())
+}
 
-fn execute_migrate(
+/// Sends expired NFTs of a collection back to their sellers. Anyone can call
+/// this; each call looks at `limit` offerings after `start_after` and
+/// reports the last one it looked at, to continue from.
+fn execute_prune_expired(
     deps: DepsMut,
-    _env:Env,
-    info: MessageInfo,
-    address: String,
-    dest:String,
-    token_ids:Vec<String>
+    env:Env,
+    nft_address:String,
+    start_after:Option<u64>,
+    limit:Option<u32>
 ) -> Result<Response, ContractError> {
-    let state = CONFIG.load(deps.storage)?;
-    deps.api.addr_validate(&address)?;
-     deps.api.addr_validate(&dest)?;
-
-    if state.owner != info.sender.to_string() {
-        return Err(ContractError::Unauthorized {});
+    if !COLLECTIONINFO.has(deps.storage, &nft_address){
+        return Err(ContractError::WrongNFTContractError {  })
     }
 
-    let mut messages:Vec<CosmosMsg> = vec![];
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let scanned = OFFERINGS
+        .prefix(&nft_address)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    let last_scanned = scanned.last().map(|(offering_id, _)| *offering_id);
+    let expired: Vec<_> = scanned.into_iter().filter(|(_, off)| is_expired(off, &env)).collect();
 
-    for token_id in token_ids{
+    let mut messages:Vec<CosmosMsg> = vec![];
+    for (offering_id, off) in expired.iter() {
+        remove_offering(deps.storage, &nft_address, *offering_id)?;
+        if off.non_custodial {
+            continue;
+        }
         messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: address.clone(),
-                funds: vec![],
-                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                    recipient: dest.clone(),
-                    token_id: token_id.clone(),
+            contract_addr: nft_address.clone(),
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: off.seller.clone(),
+                token_id: off.token_id.clone(),
             })?,
         }))
     }
 
     Ok(Response::new()
+        .add_attribute("action", "prune_expired")
+        .add_attribute("pruned", expired.len().to_string())
+        .add_attribute("last_scanned", last_scanned.map_or_else(String::new, |id| id.to_string()))
         .add_messages(messages))
 }
 
+fn execute_prune_stale(
+    deps: DepsMut,
+    env:Env,
+    nft_address:String,
+    offering_ids:Vec<u64>
+) -> Result<Response, ContractError> {
+    let mut pruned = 0;
+    for offering_id in offering_ids {
+        if let Some(off) = OFFERINGS.may_load(deps.storage, (&nft_address, offering_id))? {
+            if off.non_custodial && !is_listing_approved(deps.as_ref(), &env, &nft_address, &off.token_id, &off.seller) {
+                remove_offering(deps.storage, &nft_address, offering_id)?;
+                pruned += 1;
+            }
+        }
+    }
 
-fn execute_change_owner(
+    Ok(Response::new()
+        .add_attribute("action", "prune_stale")
+        .add_attribute("pruned", pruned.to_string()))
+}
+
+fn execute_start_auction(
     deps: DepsMut,
-    _env:Env,
+    env:Env,
     info: MessageInfo,
-    address: String,
+    seller: String,
+    token_id: String,
+    msg: StartAuction
 ) -> Result<Response, ContractError> {
-    let mut state = CONFIG.load(deps.storage)?;
+    let nft_address = info.sender.to_string();
 
-    if state.owner != info.sender.to_string() {
-        return Err(ContractError::Unauthorized {});
+    let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+
+    validate_list_price(&msg.reserve_price)?;
+    // A late bid may not hold the bidders' funds longer than the auction itself
+    if msg.duration == 0 || msg.extension_window > msg.duration {
+        return Err(ContractError::InvalidListingTime {});
     }
-    deps.api.addr_validate(&address)?;
-    state.owner = address;
-    CONFIG.save(deps.storage,&state)?;
-    Ok(Response::default())
+    let end_time = env.block.time.seconds().checked_add(msg.duration)
+        .ok_or(ContractError::InvalidListingTime {})?;
+
+    collection_info.last_auction_id += 1;
+    let auction_id = collection_info.last_auction_id;
+    COLLECTIONINFO.save(deps.storage, &nft_address, &collection_info)?;
+
+    let payment = payment_info(deps.storage, &msg.reserve_price.denom)?;
+    AUCTIONS.save(deps.storage, (&nft_address, auction_id), &Auction {
+        token_id,
+        seller: deps.api.addr_validate(&seller)?.to_string(),
+        reserve_price: msg.reserve_price,
+        payment,
+        min_bid_increment: msg.min_bid_increment,
+        end_time,
+        extension_window: msg.extension_window,
+        highest_bid: None,
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "start_auction")
+        .add_attribute("auction_id", auction_id.to_string()))
 }
 
-fn execute_set_tvl(
+fn execute_place_native_bid(
     deps: DepsMut,
-    _env:Env,
+    env:Env,
     info: MessageInfo,
-    address: String,
-    tvls: Vec<TvlInfo>,
+    nft_address: String,
+    auction_id: u64
 ) -> Result<Response, ContractError> {
-    let  state = CONFIG.load(deps.storage)?;
-
-    if state.owner != info.sender.to_string() {
-        return Err(ContractError::Unauthorized {});
-    }
-   
-    for tvl in tvls{
-        TVL.save(deps.storage, (&address,&tvl.denom), &tvl.amount)?;
-    }
+    let denom = AUCTIONS.load(deps.storage, (&nft_address, auction_id))?.reserve_price.denom;
+    let amount = native_payment(&info, &denom)?;
 
-    Ok(Response::default())
+    let payment = AssetInfo::NativeToken { denom: denom.clone() };
+    execute_place_bid(deps,env,info.sender,nft_address,auction_id,payment,denom,amount)
 }
 
-fn execute_set_offerings(
+/// Escrows a bid and refunds the bidder it replaces. A bid inside the
+/// extension window moves the end of the auction out again.
+#[allow(clippy::too_many_arguments)]
+fn execute_place_bid(
     deps: DepsMut,
-    _env:Env,
-    info:MessageInfo,
-    address: String,
-    offerings:Vec<QueryOfferingsResult>
+    env:Env,
+    bidder: Addr,
+    nft_address: String,
+    auction_id: u64,
+    payment: AssetInfo,
+    denom: String,
+    amount: Uint128
 ) -> Result<Response, ContractError> {
-    let  state = CONFIG.load(deps.storage)?;
+    let mut auction = AUCTIONS.load(deps.storage, (&nft_address, auction_id))?;
+    let now = env.block.time.seconds();
 
-    if state.owner != info.sender.to_string() {
+    if now >= auction.end_time {
+        return Err(ContractError::AuctionEnded {});
+    }
+    if auction.reserve_price.denom != denom || !payment.equal(&auction.payment) || amount.is_zero() {
+        return Err(ContractError::NotEnoughFunds {});
+    }
+    if auction.seller == bidder {
         return Err(ContractError::Unauthorized {});
     }
-    
-    for offering in offerings{
-        let crr_offering = Offering{
-            token_id:offering.token_id,
-            seller:offering.seller,
-            list_price:offering.list_price
-        };
-        OFFERINGS.save(deps.storage, (&address,&offering.id), &crr_offering)?;
+
+    let min_bid = match &auction.highest_bid {
+        Some(bid) => bid.amount + auction.min_bid_increment.max(Uint128::new(1)),
+        None => auction.reserve_price.amount,
+    };
+    if amount < min_bid {
+        return Err(ContractError::BidTooLow { min_bid });
     }
-   
-    Ok(Response::default())
+
+    let mut messages:Vec<CosmosMsg> = vec![];
+    let outbid = auction.highest_bid.replace(Bid { bidder: bidder.to_string(), amount });
+    if let Some(outbid) = outbid {
+        messages.push(PaymentAsset { info: auction.payment.clone(), amount: outbid.amount }
+            .into_msg(Addr::unchecked(outbid.bidder))?);
+    }
+
+    if auction.end_time - now < auction.extension_window {
+        auction.end_time = now.checked_add(auction.extension_window)
+            .ok_or(ContractError::InvalidListingTime {})?;
+    }
+    AUCTIONS.save(deps.storage, (&nft_address, auction_id), &auction)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "place_bid")
+        .add_attribute("auction_id", auction_id.to_string())
+        .add_attribute("amount", amount.to_string())
+        .add_attribute("end_time", auction.end_time.to_string())
+        .add_messages(messages))
 }
 
+/// Closes an auction once bidding is over. The highest bid buys the NFT
+/// with the same royalty split as a fixed-price sale; without bids the NFT
+/// goes back to the seller. Anyone can call this.
+fn execute_settle_auction(
+    deps: DepsMut,
+    env:Env,
+    nft_address: String,
+    auction_id: u64
+) -> Result<Response, ContractError> {
+    let auction = AUCTIONS.load(deps.storage, (&nft_address, auction_id))?;
+    if env.block.time.seconds() < auction.end_time {
+        return Err(ContractError::AuctionNotEnded {});
+    }
+
+    let collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+
+    AUCTIONS.remove(deps.storage, (&nft_address, auction_id));
+
+    let messages = match auction.highest_bid {
+        Some(bid) => {
+            settle_sale(deps.storage, deps.api, deps.querier, &collection_info, &auction.payment, SaleInfo {
+                from: auction.seller,
+                to: bid.bidder,
+                denom: auction.reserve_price.denom,
+                amount: bid.amount,
+                time: env.block.time.seconds(),
+                nft_address,
+                token_id: auction.token_id,
+                royalty_source: None
+            })?
+        }
+        None => vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: nft_address,
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: auction.seller,
+                token_id: auction.token_id,
+            })?,
+        })],
+    };
 
-fn execute_history(
+    Ok(Response::new()
+        .add_attribute("action", "settle_auction")
+        .add_attribute("auction_id", auction_id.to_string())
+        .add_messages(messages))
+}
+
+fn execute_cancel_auction(
     deps: DepsMut,
     _env:Env,
-    info:MessageInfo,
-    address: String,
-    histories:Vec<SaleInfo>
+    info: MessageInfo,
+    nft_address: String,
+    auction_id: u64
 ) -> Result<Response, ContractError> {
-    let  state = CONFIG.load(deps.storage)?;
+    let auction = AUCTIONS.load(deps.storage, (&nft_address, auction_id))?;
 
-    if state.owner != info.sender.to_string() {
+    if auction.seller != info.sender {
         return Err(ContractError::Unauthorized {});
     }
-
-    let mut count = 0;
-    
-    for history in histories{
-        count =  count+1;
-        SALEHISTORY.save(deps.storage, (&address,&count.to_string()), &history)?;
+    if auction.highest_bid.is_some() {
+        return Err(ContractError::AuctionHasBids {});
     }
-   
-    Ok(Response::default())
-}
-
 
-#[entry_point]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::GetStateInfo {} => to_binary(&query_state_info(deps)?),
-        QueryMsg::GetMembers {address} => to_binary(&query_get_members(deps,address)?),
-        QueryMsg::GetTradingInfo { address} => to_binary(&query_get_trading(deps,address)?),
-        QueryMsg::GetSaleHistory {address, id } => to_binary(&query_get_history(deps,address,id)?),
-        QueryMsg::GetCollectionInfo { address } =>to_binary(&query_collection_info(deps,address)?),
-        QueryMsg::GetOfferingId {address }=> to_binary(&query_get_ids(deps,address)?),
-        QueryMsg::GetOfferingPage { id,address }  => to_binary(&query_get_offering(deps,id,address)?),
-        QueryMsg::GetTvl { address, symbol }=> to_binary(&query_get_tvl(deps,address,symbol)?),
-        QueryMsg::GetTvlAll { address, symbols }=> to_binary(&query_all_tvl(deps,address,symbols)?)
-    }
-}
+    AUCTIONS.remove(deps.storage, (&nft_address, auction_id));
 
-pub fn query_state_info(deps:Deps) -> StdResult<State>{
-    let state =  CONFIG.load(deps.storage)?;
-    Ok(state)
+    Ok(Response::new()
+        .add_attribute("action", "cancel_auction")
+        .add_attribute("auction_id", auction_id.to_string())
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: nft_address,
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: auction.seller,
+                token_id: auction.token_id,
+            })?,
+        })))
 }
 
-pub fn query_collection_info(deps:Deps,address:String) -> StdResult<CollectionInfo>{
-    let collection_info =  COLLECTIONINFO.load(deps.storage,&address)?;
-    Ok(collection_info)
+fn execute_make_native_offer(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    token_id: String,
+    expires_at: u64
+) -> Result<Response, ContractError> {
+    let coin = single_native_coin(&info)?;
+    let price = Asset {
+        denom: coin.denom.clone(),
+        amount: coin.amount,
+    };
+    let payment = AssetInfo::NativeToken { denom: coin.denom.clone() };
+    execute_make_offer(deps,env,info.sender,nft_address,token_id,expires_at,price,payment)
 }
 
+/// Escrows `price`, received as `payment`, as an offer for one token of a
+/// registered collection.
+#[allow(clippy::too_many_arguments)]
+fn execute_make_offer(
+    deps: DepsMut,
+    env:Env,
+    buyer: Addr,
+    nft_address: String,
+    token_id: String,
+    expires_at: u64,
+    price: Asset,
+    payment: AssetInfo
+) -> Result<Response, ContractError> {
+    let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
 
-pub fn query_get_tvl(deps:Deps,address:String,symbol:String) -> StdResult<Uint128>{
-    let tvl = TVL.may_load(deps.storage, (&address,&symbol))?;
-    if tvl == None{
-        Ok(Uint128::new(0))
-    }
-    else{
-        Ok(tvl.unwrap())
+    validate_list_price(&price)?;
+    if expires_at <= env.block.time.seconds() {
+        return Err(ContractError::InvalidListingTime {});
     }
+
+    collection_info.last_offer_id += 1;
+    let offer_id = collection_info.last_offer_id;
+    COLLECTIONINFO.save(deps.storage, &nft_address, &collection_info)?;
+
+    OFFERS.save(deps.storage, (&nft_address, &token_id, offer_id), &Offer {
+        buyer: buyer.to_string(),
+        price,
+        payment,
+        expires_at,
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "make_offer")
+        .add_attribute("offer_id", offer_id.to_string()))
 }
 
-pub fn query_all_tvl(deps:Deps,address:String,symbols:Vec<String>) -> StdResult<Vec<TvlInfo>>{
-    let mut empty:Vec<TvlInfo> = vec![];
-    for symbol in symbols
-    {
-        let tvl = TVL.may_load(deps.storage, (&address,&symbol))?;
-        if tvl == None{
-            empty.push(TvlInfo { denom: symbol, amount: Uint128::new(0) })
-        }
-        else{
-              empty.push(TvlInfo { denom: symbol, amount: tvl.unwrap() })
-         
-        }
+/// Lets the seller of a listed token accept an offer on it. The listing is
+/// closed and the escrowed NFT goes to the buyer of the offer.
+fn execute_accept_offer_for_listing(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    offering_id: u64,
+    offer_id: u64
+) -> Result<Response, ContractError> {
+    let off = OFFERINGS.load(deps.storage, (&nft_address, offering_id))?;
+    if off.seller != info.sender {
+        return Err(ContractError::Unauthorized {});
     }
-    Ok(empty)
-}
+    assert_listing_approved(deps.as_ref(), &env, &nft_address, &off)?;
 
+    remove_offering(deps.storage, &nft_address, offering_id)?;
+    let messages = accept_offer(deps.storage,deps.api,deps.querier,&env,&nft_address,&off.token_id,offer_id,info.sender)?;
 
-pub fn query_get_members(deps:Deps,address:String) -> StdResult<Vec<UserInfo>>{
-    let members = MEMBERS.load(deps.storage,&address)?;
-    Ok(members)
+    Ok(Response::new()
+        .add_attribute("action", "accept_offer")
+        .add_attribute("offer_id", offer_id.to_string())
+        .add_messages(messages))
 }
 
-pub fn query_get_trading(deps:Deps,address:String) -> StdResult<PriceInfo>{
-    let price_info = PRICEINFO.may_load(deps.storage,&address)?;
-    if price_info ==None{
-        Ok(PriceInfo{
-            total_hope:Uint128::new(0),
-            total_juno:Uint128::new(0)
-        })
+/// Settles an offer for a token the contract now holds: the NFT goes to the
+/// buyer and the escrow is split between `seller` and the collection `MEMBERS`.
+#[allow(clippy::too_many_arguments)]
+fn accept_offer(
+    storage: &mut dyn Storage,
+    api: &dyn Api,
+    querier: QuerierWrapper,
+    env: &Env,
+    nft_address: &str,
+    token_id: &str,
+    offer_id: u64,
+    seller: Addr
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    let collection_info = COLLECTIONINFO.may_load(storage, nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+    let offer = OFFERS.load(storage, (nft_address, token_id, offer_id))?;
+
+    if env.block.time.seconds() >= offer.expires_at {
+        return Err(ContractError::OfferExpired {});
     }
-    else{
-    Ok(price_info.unwrap())}
+
+    OFFERS.remove(storage, (nft_address, token_id, offer_id));
+
+    Ok(settle_sale(storage, api, querier, &collection_info, &offer.payment, SaleInfo {
+        from: seller.to_string(),
+        to: offer.buyer,
+        denom: offer.price.denom,
+        amount: offer.price.amount,
+        time: env.block.time.seconds(),
+        nft_address: nft_address.to_string(),
+        token_id: token_id.to_string(),
+        royalty_source: None
+    })?)
 }
 
-// pub fn query_get_offerings(deps:Deps) -> StdResult<OfferingsResponse>{
-//     let res: StdResult<Vec<QueryOfferingsResult>> = OFFERINGS
-//         .range(deps.storage, None, None, Order::Ascending)
-//         .map(|kv_item| parse_offering(deps, kv_item  ))
-//         .collect();
-//     Ok(OfferingsResponse {
-//         offerings: res?, // Placeholder
-//     })
-// }
+/// Returns the escrow of an offer to its buyer. The buyer can cancel at any
+/// time; once the offer has expired anyone can trigger the refund.
+fn execute_cancel_offer(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    token_id: String,
+    offer_id: u64
+) -> Result<Response, ContractError> {
+    let offer = OFFERS.load(deps.storage, (&nft_address, &token_id, offer_id))?;
 
-// fn parse_offering(
-//     deps:Deps,
-//     item: StdResult<((String,String),Offering)>,
-// ) -> StdResult<QueryOfferingsResult> {
-//     item.and_then(|((address,k), offering)| {
-//         Ok(QueryOfferingsResult {
-//             id: k,
-//             token_id: offering.token_id,
-//             list_price: offering.list_price,
-//             seller: deps.api.addr_validate(&offering.seller)?.to_string(),
-//         })
-//     })
-// }
+    if offer.buyer != info.sender && env.block.time.seconds() < offer.expires_at {
+        return Err(ContractError::Unauthorized {});
+    }
 
+    OFFERS.remove(deps.storage, (&nft_address, &token_id, offer_id));
 
-pub fn query_get_ids(deps:Deps,address: String) -> StdResult<Vec<String>>{
-     let token_id:StdResult<Vec<String>>  = OFFERINGS
-        .keys(deps.storage, None, None, Order::Ascending)
-        .filter(|keys|keys.as_ref().unwrap().0 == address.clone())
-        .map(|keys|parse_keys(deps, keys))
-        .collect();
-    Ok(token_id?)
+    Ok(Response::new()
+        .add_attribute("action", "cancel_offer")
+        .add_attribute("offer_id", offer_id.to_string())
+        .add_message(PaymentAsset { info: offer.payment, amount: offer.price.amount }
+            .into_msg(Addr::unchecked(offer.buyer))?))
 }
 
-fn parse_keys(
-    _deps:Deps,
-    item: StdResult<(String,String)>,
-) -> StdResult<String> {
-    item.and_then(|(_address,token_id)| {
-        Ok(token_id)
-    })
+fn execute_place_native_collection_bid(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    price: Uint128,
+    quantity: u64,
+    traits: Vec<Trait>
+) -> Result<Response, ContractError> {
+    let coin = single_native_coin(&info)?;
+    let payment = Asset {
+        denom: coin.denom.clone(),
+        amount: coin.amount,
+    };
+    let payment_asset = AssetInfo::NativeToken { denom: coin.denom.clone() };
+    execute_place_collection_bid(deps,env,info.sender,nft_address,price,quantity,traits,payment,payment_asset)
 }
 
+/// Escrows `quantity * price` for as many tokens of a registered collection,
+/// optionally restricted to tokens carrying all of `traits`. `payment_asset`
+/// is the coin or cw20 contract the escrow was received in.
+#[allow(clippy::too_many_arguments)]
+fn execute_place_collection_bid(
+    deps: DepsMut,
+    _env:Env,
+    buyer: Addr,
+    nft_address: String,
+    price: Uint128,
+    quantity: u64,
+    traits: Vec<Trait>,
+    payment: Asset,
+    payment_asset: AssetInfo
+) -> Result<Response, ContractError> {
+    let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+
+    if quantity == 0 {
+        return Err(ContractError::InvalidQuantity {});
+    }
+    let price = Asset { denom: payment.denom, amount: price };
+    validate_list_price(&price)?;
+    let total = price.amount.checked_mul(Uint128::from(quantity)).map_err(StdError::from)?;
+    assert_exact_payment(payment.amount, total)?;
+
+    collection_info.last_collection_bid_id += 1;
+    let bid_id = collection_info.last_collection_bid_id;
+    COLLECTIONINFO.save(deps.storage, &nft_address, &collection_info)?;
+
+    COLLECTION_BIDS.save(deps.storage, (&nft_address, bid_id), &CollectionBid {
+        buyer: buyer.to_string(),
+        price,
+        payment: payment_asset,
+        quantity,
+        traits,
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "place_collection_bid")
+        .add_attribute("bid_id", bid_id.to_string()))
+}
+
+/// Sells the token a holder sent in to a collection bid, one unit at a time.
+fn execute_fill_collection_bid(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    seller: String,
+    token_id: String,
+    bid_id: u64
+) -> Result<Response, ContractError> {
+    let nft_address = info.sender.to_string();
+    let collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+    let mut bid = COLLECTION_BIDS.load(deps.storage, (&nft_address, bid_id))?;
+
+    if !bid.traits.is_empty() {
+        let nft_info: NftInfoResponse<Option<Metadata>> = deps.querier.query_wasm_smart(
+            &nft_address,
+            &Cw721QueryMsg::NftInfo { token_id: token_id.clone() }
+        )?;
+        let attributes = nft_info.extension.and_then(|meta| meta.attributes).unwrap_or_default();
+        if !bid.traits.iter().all(|t| attributes.contains(t)) {
+            return Err(ContractError::TraitMismatch {});
+        }
+    }
+
+    bid.quantity -= 1;
+    if bid.quantity == 0 {
+        COLLECTION_BIDS.remove(deps.storage, (&nft_address, bid_id));
+    } else {
+        COLLECTION_BIDS.save(deps.storage, (&nft_address, bid_id), &bid)?;
+    }
+
+    let messages = settle_sale(deps.storage, deps.api, deps.querier, &collection_info, &bid.payment, SaleInfo {
+        from: deps.api.addr_validate(&seller)?.to_string(),
+        to: bid.buyer,
+        denom: bid.price.denom,
+        amount: bid.price.amount,
+        time: env.block.time.seconds(),
+        nft_address,
+        token_id,
+        royalty_source: None
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "fill_collection_bid")
+        .add_attribute("bid_id", bid_id.to_string())
+        .add_attribute("remaining", bid.quantity.to_string())
+        .add_messages(messages))
+}
+
+/// Closes a collection bid and refunds the escrow for the unfilled units.
+fn execute_cancel_collection_bid(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    bid_id: u64
+) -> Result<Response, ContractError> {
+    let bid = COLLECTION_BIDS.load(deps.storage, (&nft_address, bid_id))?;
+    if bid.buyer != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    COLLECTION_BIDS.remove(deps.storage, (&nft_address, bid_id));
+
+    let refund = bid.price.amount * Uint128::from(bid.quantity);
+    Ok(Response::new()
+        .add_attribute("action", "cancel_collection_bid")
+        .add_attribute("bid_id", bid_id.to_string())
+        .add_message(PaymentAsset { info: bid.payment, amount: refund }.into_msg(info.sender)?))
+}
+
+fn execute_create_bundle(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    price: Asset,
+    items: Vec<BundleItem>
+) -> Result<Response, ContractError> {
+    validate_list_price(&price)?;
+    if items.is_empty() {
+        return Err(ContractError::InvalidBundle {});
+    }
+
+    let mut total = Decimal::zero();
+    for (i, item) in items.iter().enumerate() {
+        if !COLLECTIONINFO.has(deps.storage, &item.nft_address) {
+            return Err(ContractError::WrongNFTContractError {});
+        }
+        if items[..i].iter().any(|other| other.nft_address == item.nft_address && other.token_id == item.token_id) {
+            return Err(ContractError::InvalidBundle {});
+        }
+        total += item.allocation;
+    }
+    if total != Decimal::one() {
+        return Err(ContractError::PortionError {});
+    }
+
+    let bundle_id = LAST_BUNDLE_ID.may_load(deps.storage)?.unwrap_or_default() + 1;
+    LAST_BUNDLE_ID.save(deps.storage, &bundle_id)?;
+
+    let payment = payment_info(deps.storage, &price.denom)?;
+    BUNDLES.save(deps.storage, bundle_id, &Bundle {
+        seller: info.sender.to_string(),
+        price,
+        payment,
+        items: items.into_iter().map(|item| BundleItem { deposited: false, ..item }).collect(),
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "create_bundle")
+        .add_attribute("bundle_id", bundle_id.to_string()))
+}
+
+/// Escrows one token of a bundle. The bundle goes on sale with the last one.
+fn execute_deposit_bundle(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    sender: String,
+    token_id: String,
+    bundle_id: u64
+) -> Result<Response, ContractError> {
+    let mut bundle = BUNDLES.load(deps.storage, bundle_id)?;
+    if bundle.seller != sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let item = bundle.items.iter_mut()
+        .find(|item| item.nft_address == info.sender && item.token_id == token_id && !item.deposited)
+        .ok_or(ContractError::InvalidBundle {})?;
+    item.deposited = true;
+    BUNDLES.save(deps.storage, bundle_id, &bundle)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "deposit_bundle")
+        .add_attribute("bundle_id", bundle_id.to_string())
+        .add_attribute("active", bundle.is_active().to_string()))
+}
+
+fn execute_buy_native_bundle(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    bundle_id: u64
+) -> Result<Response, ContractError> {
+    let bundle = BUNDLES.load(deps.storage, bundle_id)?;
+    let payment_asset = AssetInfo::NativeToken { denom: bundle.price.denom.clone() };
+    PaymentAsset { info: payment_asset.clone(), amount: bundle.price.amount }
+        .assert_sent_native_token_balance(&info)?;
+    execute_buy_bundle(deps,env,info.sender,bundle_id,bundle.price,payment_asset)
+}
+
+/// Transfers every token of the bundle to the buyer. The price is split over
+/// the items by allocation and each share is paid out under its collection's
+/// royalty, the last item taking the rounding remainder.
+fn execute_buy_bundle(
+    deps: DepsMut,
+    env:Env,
+    buyer: Addr,
+    bundle_id: u64,
+    payment: Asset,
+    payment_asset: AssetInfo
+) -> Result<Response, ContractError> {
+    let bundle = BUNDLES.load(deps.storage, bundle_id)?;
+    if !bundle.is_active() {
+        return Err(ContractError::BundleNotActive {});
+    }
+    if payment.denom != bundle.price.denom || !payment_asset.equal(&bundle.payment) {
+        return Err(ContractError::UnexpectedDenom { denom: payment.denom });
+    }
+    assert_exact_payment(payment.amount, bundle.price.amount)?;
+
+    BUNDLES.remove(deps.storage, bundle_id);
+
+    let mut messages = vec![];
+    let mut remaining = bundle.price.amount;
+    let last = bundle.items.len() - 1;
+    for (i, item) in bundle.items.into_iter().enumerate() {
+        let share = if i == last { remaining } else { bundle.price.amount * item.allocation };
+        remaining -= share;
+
+        let collection_info = COLLECTIONINFO.load(deps.storage, &item.nft_address)?;
+        messages.extend(settle_sale(deps.storage, deps.api, deps.querier, &collection_info, &bundle.payment, SaleInfo {
+            from: bundle.seller.clone(),
+            to: buyer.to_string(),
+            denom: bundle.price.denom.clone(),
+            amount: share,
+            time: env.block.time.seconds(),
+            nft_address: item.nft_address,
+            token_id: item.token_id,
+            royalty_source: None
+        })?);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "buy_bundle")
+        .add_attribute("bundle_id", bundle_id.to_string())
+        .add_messages(messages))
+}
+
+/// Removes a bundle and returns whatever tokens were already escrowed.
+fn execute_cancel_bundle(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    bundle_id: u64
+) -> Result<Response, ContractError> {
+    let bundle = BUNDLES.load(deps.storage, bundle_id)?;
+    if bundle.seller != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    BUNDLES.remove(deps.storage, bundle_id);
+
+    let seller = bundle.seller;
+    let messages = bundle.items.into_iter()
+        .filter(|item| item.deposited)
+        .map(|item| Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: item.nft_address,
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: seller.clone(),
+                token_id: item.token_id,
+            })?,
+        })))
+        .collect::<StdResult<Vec<CosmosMsg>>>()?;
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_bundle")
+        .add_attribute("bundle_id", bundle_id.to_string())
+        .add_messages(messages))
+}
+
+fn execute_create_swap(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    offered_nfts: Vec<NftRef>,
+    wanted_nfts: Vec<NftRef>,
+    wanted_funds: Vec<PaymentAsset>,
+    counterparty: Option<String>
+) -> Result<Response, ContractError> {
+    if wanted_nfts.is_empty() && wanted_funds.is_empty() {
+        return Err(ContractError::InvalidSwap {});
+    }
+    for (i, nft) in offered_nfts.iter().enumerate() {
+        if offered_nfts[..i].contains(nft) {
+            return Err(ContractError::InvalidSwap {});
+        }
+    }
+    for nft in offered_nfts.iter().chain(wanted_nfts.iter()) {
+        if !COLLECTIONINFO.has(deps.storage, &nft.nft_address) {
+            return Err(ContractError::WrongNFTContractError {});
+        }
+    }
+    for (i, asset) in wanted_funds.iter().enumerate() {
+        if asset.amount.is_zero() {
+            return Err(ContractError::InvalidPrice {});
+        }
+        if wanted_funds[..i].iter().any(|other| other.info.equal(&asset.info)) {
+            return Err(ContractError::InvalidSwap {});
+        }
+        if let AssetInfo::Token { contract_addr } = &asset.info {
+            if !TOKENADDRESS.has(deps.storage, contract_addr) {
+                return Err(ContractError::WrongTokenContractError {});
+            }
+        }
+    }
+    let counterparty = counterparty
+        .map(|addr| deps.api.addr_validate(&addr).map(|addr| addr.to_string()))
+        .transpose()?;
+    if info.funds.iter().any(|coin| coin.amount.is_zero()) {
+        return Err(ContractError::ZeroFunds {});
+    }
+    if offered_nfts.is_empty() && info.funds.is_empty() {
+        return Err(ContractError::InvalidSwap {});
+    }
+
+    let swap_id = LAST_SWAP_ID.may_load(deps.storage)?.unwrap_or_default() + 1;
+    LAST_SWAP_ID.save(deps.storage, &swap_id)?;
+
+    SWAPS.save(deps.storage, swap_id, &Swap {
+        maker: info.sender.to_string(),
+        counterparty,
+        offered_nfts,
+        escrowed_nfts: vec![],
+        offered_funds: info.funds.into_iter()
+            .map(|coin| PaymentAsset { info: AssetInfo::NativeToken { denom: coin.denom }, amount: coin.amount })
+            .collect(),
+        wanted_nfts,
+        wanted_funds,
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "create_swap")
+        .add_attribute("swap_id", swap_id.to_string()))
+}
+
+/// Escrows one of the NFTs the maker offers in a swap.
+fn execute_deposit_swap(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    sender: String,
+    token_id: String,
+    swap_id: u64
+) -> Result<Response, ContractError> {
+    let mut swap = SWAPS.load(deps.storage, swap_id)?;
+    if swap.maker != sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let nft = NftRef { nft_address: info.sender.to_string(), token_id };
+    if !swap.offered_nfts.contains(&nft) || swap.escrowed_nfts.contains(&nft) {
+        return Err(ContractError::InvalidSwap {});
+    }
+    swap.escrowed_nfts.push(nft);
+    SWAPS.save(deps.storage, swap_id, &swap)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "deposit_swap")
+        .add_attribute("swap_id", swap_id.to_string())
+        .add_attribute("active", swap.is_active().to_string()))
+}
+
+/// Adds a cw20 amount to the maker's side of a swap.
+fn execute_fund_swap(
+    deps: DepsMut,
+    _env:Env,
+    sender: String,
+    swap_id: u64,
+    payment: PaymentAsset
+) -> Result<Response, ContractError> {
+    let mut swap = SWAPS.load(deps.storage, swap_id)?;
+    if swap.maker != sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    match swap.offered_funds.iter_mut().find(|asset| asset.info.equal(&payment.info)) {
+        Some(asset) => asset.amount += payment.amount,
+        None => swap.offered_funds.push(payment),
+    }
+    SWAPS.save(deps.storage, swap_id, &swap)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "fund_swap")
+        .add_attribute("swap_id", swap_id.to_string()))
+}
+
+/// Completes a swap in one go: the escrowed side goes to the taker, and the
+/// wanted NFTs and cw20 amounts are moved from the taker to the maker under
+/// the taker's approvals, together with the attached native funds.
+fn execute_take_swap(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    swap_id: u64
+) -> Result<Response, ContractError> {
+    let swap = SWAPS.load(deps.storage, swap_id)?;
+    if !swap.is_active() {
+        return Err(ContractError::SwapNotActive {});
+    }
+    if swap.counterparty.as_ref().is_some_and(|counterparty| *counterparty != info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // Totals per denom, so a swap stored with repeated assets cannot be paid
+    // out of other users' escrow.
+    let mut wanted_native: Vec<(&str, Uint128)> = vec![];
+    for asset in swap.wanted_funds.iter() {
+        if let AssetInfo::NativeToken { denom } = &asset.info {
+            match wanted_native.iter_mut().find(|(wanted, _)| wanted == denom) {
+                Some((_, amount)) => *amount += asset.amount,
+                None => wanted_native.push((denom, asset.amount)),
+            }
+        }
+    }
+    for (denom, amount) in wanted_native {
+        assert_exact_payment(sent_native_amount(&info, denom)?, amount)?;
+    }
+    let unwanted = info.funds.iter().find(|coin| !swap.wanted_funds.iter().any(|asset|
+        asset.info.equal(&AssetInfo::NativeToken { denom: coin.denom.clone() })));
+    if let Some(coin) = unwanted {
+        return Err(ContractError::UnexpectedDenom { denom: coin.denom.clone() });
+    }
+    // The marketplace moves the wanted NFTs itself, so they must be the
+    // taker's and not something held in escrow here.
+    for nft in swap.wanted_nfts.iter() {
+        let owner: OwnerOfResponse = deps.querier.query_wasm_smart(&nft.nft_address, &Cw721QueryMsg::OwnerOf {
+            token_id: nft.token_id.clone(),
+            include_expired: Some(false)
+        })?;
+        if owner.owner != info.sender {
+            return Err(ContractError::Unauthorized {});
+        }
+    }
+
+    SWAPS.remove(deps.storage, swap_id);
+
+    let maker = Addr::unchecked(swap.maker);
+    let mut messages = vec![];
+    for nft in swap.offered_nfts {
+        messages.push(transfer_nft_msg(nft, &info.sender)?);
+    }
+    for asset in swap.offered_funds {
+        messages.push(asset.into_msg(info.sender.clone())?);
+    }
+    for nft in swap.wanted_nfts {
+        messages.push(transfer_nft_msg(nft, &maker)?);
+    }
+    for asset in swap.wanted_funds {
+        messages.push(match asset.info {
+            AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                funds: vec![],
+                msg: to_binary(&Cw20ExecuteMsg::TransferFrom {
+                    owner: info.sender.to_string(),
+                    recipient: maker.to_string(),
+                    amount: asset.amount,
+                })?,
+            }),
+            AssetInfo::NativeToken { .. } => asset.into_msg(maker.clone())?,
+        });
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "take_swap")
+        .add_attribute("swap_id", swap_id.to_string())
+        .add_messages(messages))
+}
+
+/// Closes a swap and returns everything the maker escrowed.
+fn execute_cancel_swap(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    swap_id: u64
+) -> Result<Response, ContractError> {
+    let swap = SWAPS.load(deps.storage, swap_id)?;
+    if swap.maker != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    SWAPS.remove(deps.storage, swap_id);
+
+    let mut messages = vec![];
+    for nft in swap.escrowed_nfts {
+        messages.push(transfer_nft_msg(nft, &info.sender)?);
+    }
+    for asset in swap.offered_funds {
+        messages.push(asset.into_msg(info.sender.clone())?);
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_swap")
+        .add_attribute("swap_id", swap_id.to_string())
+        .add_messages(messages))
+}
+
+fn transfer_nft_msg(nft: NftRef, recipient: &Addr) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: nft.nft_address,
+        funds: vec![],
+        msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+            recipient: recipient.to_string(),
+            token_id: nft.token_id,
+        })?,
+    }))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn execute_add_mint_template(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    token_id: String,
+    token_uri: Option<String>,
+    price: Asset
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+    validate_list_price(&price)?;
+    if token_id.starts_with(LAUNCH_TOKEN_PREFIX) {
+        return Err(ContractError::ReservedTokenId {});
+    }
+
+    collection_info.last_mint_template_id += 1;
+    let template_id = collection_info.last_mint_template_id;
+    COLLECTIONINFO.save(deps.storage, &nft_address, &collection_info)?;
+
+    MINT_TEMPLATES.save(deps.storage, (&nft_address, template_id), &MintTemplate {
+        token_id,
+        token_uri,
+        price,
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "add_mint_template")
+        .add_attribute("template_id", template_id.to_string()))
+}
+
+fn execute_remove_mint_template(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    template_id: u64
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    MINT_TEMPLATES.load(deps.storage, (&nft_address, template_id))?;
+    MINT_TEMPLATES.remove(deps.storage, (&nft_address, template_id));
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_mint_template")
+        .add_attribute("template_id", template_id.to_string()))
+}
+
+fn execute_buy_native_mint(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    template_id: u64
+) -> Result<Response, ContractError> {
+    let price = MINT_TEMPLATES.load(deps.storage, (&nft_address, template_id))?.price;
+    PaymentAsset { info: AssetInfo::NativeToken { denom: price.denom.clone() }, amount: price.amount }
+        .assert_sent_native_token_balance(&info)?;
+    execute_buy_mint(deps,env,info.sender,nft_address,template_id,price)
+}
+
+/// Mints a template's token straight to the buyer. With no seller, the whole
+/// price is split over the collection `MEMBERS`.
+fn execute_buy_mint(
+    deps: DepsMut,
+    env:Env,
+    buyer: Addr,
+    nft_address: String,
+    template_id: u64,
+    payment: Asset
+) -> Result<Response, ContractError> {
+    let template = MINT_TEMPLATES.load(deps.storage, (&nft_address, template_id))?;
+    if payment.denom != template.price.denom {
+        return Err(ContractError::UnexpectedDenom { denom: payment.denom });
+    }
+    assert_exact_payment(payment.amount, template.price.amount)?;
+
+    MINT_TEMPLATES.remove(deps.storage, (&nft_address, template_id));
+
+    let mut messages:Vec<CosmosMsg> = vec![mint_msg(&nft_address, &template.token_id, &buyer, template.token_uri)?];
+    messages.extend(member_payouts(deps.storage, &nft_address, &template.price)?);
+    let native = payment_info(deps.storage, &payment.denom)?.is_native_token();
+    record_sale(deps.storage, &SaleInfo {
+        from: env.contract.address.to_string(),
+        to: buyer.to_string(),
+        denom: template.price.denom,
+        amount: template.price.amount,
+        time: env.block.time.seconds(),
+        nft_address,
+        token_id: template.token_id,
+        royalty_source: None
+    }, native)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "buy_mint")
+        .add_attribute("template_id", template_id.to_string())
+        .add_messages(messages))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn execute_set_launch(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    phases: Vec<LaunchPhase>,
+    supply_cap: u64,
+    base_token_uri: Option<String>
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if !COLLECTIONINFO.has(deps.storage, &nft_address) {
+        return Err(ContractError::WrongNFTContractError {});
+    }
+    for (i, phase) in phases.iter().enumerate() {
+        validate_list_price(&phase.price)?;
+        if phase.start_time >= phase.end_time || phase.per_wallet_limit == Some(0) {
+            return Err(ContractError::InvalidLaunch {});
+        }
+        if phases[..i].iter().any(|other| phase.start_time < other.end_time && other.start_time < phase.end_time) {
+            return Err(ContractError::InvalidLaunch {});
+        }
+        if let Some(allowlist) = &phase.allowlist {
+            for address in allowlist {
+                deps.api.addr_validate(address)?;
+            }
+        }
+        if phase.merkle_root.as_ref().is_some_and(|root| !is_valid_root(root)) {
+            return Err(ContractError::InvalidMerkleRoot {});
+        }
+    }
+
+    // Reconfiguring keeps the running token count
+    let existing = LAUNCHES.may_load(deps.storage, &nft_address)?;
+    let minted = existing.as_ref().map_or(0, |launch| launch.minted);
+    if supply_cap < minted {
+        return Err(ContractError::InvalidLaunch {});
+    }
+    // Wallet counts are keyed by phase index, they only carry over while
+    // the phases stay the same
+    if existing.is_some_and(|launch| launch.phases != phases) {
+        let counted = LAUNCH_MINTS.sub_prefix(&nft_address)
+            .keys(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?;
+        for (phase_id, wallet) in counted {
+            LAUNCH_MINTS.remove(deps.storage, (&nft_address, phase_id, &wallet));
+        }
+    }
+    LAUNCHES.save(deps.storage, &nft_address, &Launch {
+        phases,
+        supply_cap,
+        base_token_uri,
+        minted,
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_launch")
+        .add_attribute("nft_address", nft_address))
+}
+
+fn execute_launch_native_mint(
+    deps: DepsMut,
+    env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    quantity: u32,
+    proof: Option<Vec<HexBinary>>
+) -> Result<Response, ContractError> {
+    let launch = LAUNCHES.load(deps.storage, &nft_address)?;
+    let (_, phase) = launch.active_phase(env.block.time.seconds())
+        .ok_or(ContractError::LaunchNotActive {})?;
+    let denom = phase.price.denom.clone();
+    let amount = native_payment(&info, &denom)?;
+    execute_launch_mint(deps,env,info.sender,nft_address,quantity,proof,Asset { denom, amount })
+}
+
+/// Mints `quantity` tokens of the running phase to `minter`. Proceeds go to
+/// the collection `MEMBERS`.
+#[allow(clippy::too_many_arguments)]
+fn execute_launch_mint(
+    deps: DepsMut,
+    env:Env,
+    minter: Addr,
+    nft_address: String,
+    quantity: u32,
+    proof: Option<Vec<HexBinary>>,
+    payment: Asset
+) -> Result<Response, ContractError> {
+    let mut launch = LAUNCHES.load(deps.storage, &nft_address)?;
+    let (phase_id, phase) = launch.active_phase(env.block.time.seconds())
+        .ok_or(ContractError::LaunchNotActive {})?;
+    let phase = phase.clone();
+    if quantity == 0 {
+        return Err(ContractError::InvalidQuantity {});
+    }
+    if !phase_allows(&phase, &minter, proof.as_deref()) {
+        return Err(ContractError::NotAllowlisted {});
+    }
+
+    let minted_by_wallet = LAUNCH_MINTS.may_load(deps.storage, (&nft_address, phase_id, minter.as_str()))?
+        .unwrap_or_default() + quantity;
+    if phase.per_wallet_limit.is_some_and(|limit| minted_by_wallet > limit) {
+        return Err(ContractError::MintLimitReached {});
+    }
+    if launch.minted + quantity as u64 > launch.supply_cap {
+        return Err(ContractError::SoldOut {});
+    }
+
+    let price = Asset {
+        denom: phase.price.denom.clone(),
+        amount: phase.price.amount.checked_mul(Uint128::from(quantity)).map_err(StdError::from)?
+    };
+    if payment.denom != price.denom {
+        return Err(ContractError::UnexpectedDenom { denom: payment.denom });
+    }
+    assert_exact_payment(payment.amount, price.amount)?;
+
+    let native = payment_info(deps.storage, &price.denom)?.is_native_token();
+    let mut messages:Vec<CosmosMsg> = vec![];
+    for _ in 0..quantity {
+        launch.minted += 1;
+        let token_id = format!("{}{}", LAUNCH_TOKEN_PREFIX, launch.minted);
+        let token_uri = launch.base_token_uri.as_ref().map(|base| format!("{}/{}", base, launch.minted));
+        messages.push(mint_msg(&nft_address, &token_id, &minter, token_uri)?);
+        record_sale(deps.storage, &SaleInfo {
+            from: env.contract.address.to_string(),
+            to: minter.to_string(),
+            denom: phase.price.denom.clone(),
+            amount: phase.price.amount,
+            time: env.block.time.seconds(),
+            nft_address: nft_address.clone(),
+            token_id,
+            royalty_source: None
+        }, native)?;
+    }
+    messages.extend(member_payouts(deps.storage, &nft_address, &price)?);
+
+    LAUNCHES.save(deps.storage, &nft_address, &launch)?;
+    LAUNCH_MINTS.save(deps.storage, (&nft_address, phase_id, minter.as_str()), &minted_by_wallet)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "launch_mint")
+        .add_attribute("phase", phase.name)
+        .add_attribute("quantity", quantity.to_string())
+        .add_messages(messages))
+}
+
+/// A phase with an allowlist or Merkle root only admits wallets on either.
+fn phase_allows(phase: &LaunchPhase, minter: &Addr, proof: Option<&[HexBinary]>) -> bool {
+    if phase.allowlist.is_none() && phase.merkle_root.is_none() {
+        return true;
+    }
+    phase.allowlist.as_ref().is_some_and(|allowlist| allowlist.iter().any(|address| address == minter.as_str()))
+        || phase.merkle_root.as_ref().zip(proof).is_some_and(|(root, proof)| verify_proof(root, minter.as_str(), proof))
+}
+
+fn mint_msg(nft_address: &str, token_id: &str, owner: &Addr, token_uri: Option<String>) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: nft_address.to_string(),
+        funds: vec![],
+        msg: to_binary(&cw721_base::ExecuteMsg::Mint(cw721_base::MintMsg::<cw721_base::Extension> {
+            token_id: token_id.to_string(),
+            owner: owner.to_string(),
+            token_uri,
+            extension: None,
+        }))?,
+    }))
+}
+
+/// Pays a primary sale out to the collection `MEMBERS` by portion, after
+/// taking the protocol fee.
+fn member_payouts(storage: &mut dyn Storage, nft_address: &str, price: &Asset) -> StdResult<Vec<CosmosMsg>> {
+    let collection_info = COLLECTIONINFO.load(storage, nft_address)?;
+    let payment = payment_info(storage, &price.denom)?;
+    let fee = protocol_fee(storage, &collection_info, price.amount)?;
+    let shares = split_by_portion(price.amount.checked_sub(fee)?, MEMBERS.load(storage, nft_address)?);
+    let messages = pay_shares(storage, collection_info.pull_payments, &payment, &price.denom, shares)?;
+    accrue_fee(storage, &price.denom, fee)?;
+    Ok(messages)
+}
+
+/// Deletes a sold or withdrawn offering. Offering IDs are never handed out
+/// again, so only the live offering count of the collection goes down.
+fn remove_offering(
+    storage: &mut dyn Storage,
+    nft_address: &str,
+    offering_id: u64
+) -> StdResult<()> {
+    OFFERINGS.remove(storage, (nft_address,offering_id));
+    COLLECTIONINFO.update(storage, nft_address,
+        |collection_info|->StdResult<_>{
+            let mut collection_info = collection_info
+                .ok_or_else(|| StdError::not_found("CollectionInfo"))?;
+            collection_info.offering_id = collection_info.offering_id.saturating_sub(1);
+            Ok(collection_info)
+        })?;
+    Ok(())
+}
+
+
+#[allow(clippy::too_many_arguments)]
+fn execute_add_collection(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    royalty_potion: Decimal,
+    members: Vec<UserInfo>,
+    nft_address:String,
+    offering_id:u64,
+    sale_id:u64
+)->Result<Response,ContractError>{
+
+    let state = CONFIG.load(deps.storage)?;
+
+    deps.api.addr_validate(&nft_address)?;
+
+    if info.sender != state.owner{
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut sum_portion = Decimal::zero();
+
+    for item in members.clone() {
+        sum_portion += item.portion;
+        deps.api.addr_validate(&item.address)?;
+    }
+
+    if sum_portion != Decimal::one(){
+        return Err(ContractError::WrongPortionError { })
+    }
+
+    let collection_info = CollectionInfo{
+        nft_address:nft_address.clone(),
+        offering_id,
+        sale_id,
+        royalty_portion:royalty_potion,
+        last_offering_id:offering_id,
+        last_auction_id:0,
+        last_offer_id:0,
+        last_collection_bid_id:0,
+        last_mint_template_id:0,
+        protocol_fee:None,
+        pull_payments:false,
+        referral_bps:None,
+        cw2981_royalties:false
+    };
+    assert_cuts_fit(&state, &collection_info)?;
+
+    MEMBERS.save(deps.storage,&nft_address, &members)?;
+    COLLECTIONINFO.save(deps.storage,&nft_address,&collection_info)?;
+    Ok(Response::default())
+}
+
+
+fn execute_update_collection(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    royalty_potion: Decimal,
+    members: Vec<UserInfo>,
+    nft_address:String
+)->Result<Response,ContractError>{
+
+    let state = CONFIG.load(deps.storage)?;
+
+    deps.api.addr_validate(&nft_address)?;
+
+    if info.sender != state.owner{
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let collection_info = COLLECTIONINFO.may_load(deps.storage,&nft_address)?
+        .ok_or(ContractError::WrongCollection {})?;
+
+    let mut sum_portion = Decimal::zero();
+
+    for item in members.clone() {
+        sum_portion += item.portion;
+        deps.api.addr_validate(&item.address)?;
+    }
+
+    if sum_portion != Decimal::one(){
+        return Err(ContractError::WrongPortionError { })
+    }
+
+    let collection_info = CollectionInfo{
+        royalty_portion:royalty_potion,
+        ..collection_info
+    };
+    assert_cuts_fit(&state, &collection_info)?;
+
+    MEMBERS.save(deps.storage,&nft_address, &members)?;
+    COLLECTIONINFO.save(deps.storage,&nft_address,&collection_info)?;
+    Ok(Response::default())
+}
+
+
+fn execute_token_address(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    symbol:String,
+    address: String,
+) -> Result<Response, ContractError> {
+    let  state = CONFIG.load(deps.storage)?;
+    deps.api.addr_validate(&address)?;
+
+     if info.sender != state.owner{
+        return Err(ContractError::Unauthorized {});
+    }
+    // Symbols name the currency of prices, so each may belong to one contract
+    let taken = TOKENADDRESS
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?
+        .into_iter()
+        .any(|(other, other_symbol)| other != address && other_symbol == symbol);
+    if taken {
+        return Err(ContractError::DuplicateSymbol {});
+    }
+
+    TOKENADDRESS.save(deps.storage,&address,&symbol)?;
+
+    CONFIG.save(deps.storage, &state)?;
+    Ok(Response::default())
+}
+
+
+fn execute_fix_nft(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    address: String,
+    token_id:String
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    deps.api.addr_validate(&address)?;
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    Ok(Response::new()
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: address,
+                funds: vec![],
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: info.sender.to_string(),
+                    token_id,
+            })?,
+        })))
+}
+
+
+fn execute_migrate(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    address: String,
+    dest:String,
+    token_ids:Vec<String>
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+    deps.api.addr_validate(&address)?;
+     deps.api.addr_validate(&dest)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut messages:Vec<CosmosMsg> = vec![];
+
+    for token_id in token_ids{
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: address.clone(),
+                funds: vec![],
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: dest.clone(),
+                    token_id: token_id.clone(),
+            })?,
+        }))
+    }
+
+    Ok(Response::new()
+        .add_messages(messages))
+}
+
+
+fn execute_change_owner(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    deps.api.addr_validate(&address)?;
+    state.owner = address;
+    CONFIG.save(deps.storage,&state)?;
+    Ok(Response::default())
+}
+
+fn execute_set_oracle(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    address: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    state.oracle = address
+        .map(|addr| deps.api.addr_validate(&addr).map(|addr| addr.to_string()))
+        .transpose()?;
+    CONFIG.save(deps.storage,&state)?;
+    Ok(Response::default())
+}
+
+fn execute_set_protocol_fee(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    nft_address: Option<String>,
+    fee: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if fee.is_some_and(|fee| fee > Decimal::percent(MAX_PROTOCOL_FEE_PERCENT)) {
+        return Err(ContractError::InvalidFee {});
+    }
+    match nft_address {
+        Some(nft_address) => {
+            let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+                .ok_or(ContractError::WrongNFTContractError {})?;
+            collection_info.protocol_fee = fee;
+            assert_cuts_fit(&state, &collection_info)?;
+            COLLECTIONINFO.save(deps.storage, &nft_address, &collection_info)?;
+        }
+        None => {
+            state.protocol_fee = fee.unwrap_or_default();
+            assert_all_cuts_fit(deps.storage, &state)?;
+            CONFIG.save(deps.storage,&state)?;
+        }
+    }
+    Ok(Response::new()
+        .add_attribute("action", "set_protocol_fee"))
+}
+
+fn execute_set_referral_fee(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    nft_address: Option<String>,
+    bps: Option<u64>,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if bps.is_some_and(|bps| bps > MAX_REFERRAL_BPS) {
+        return Err(ContractError::InvalidFee {});
+    }
+    match nft_address {
+        Some(nft_address) => {
+            let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+                .ok_or(ContractError::WrongNFTContractError {})?;
+            collection_info.referral_bps = bps;
+            assert_cuts_fit(&state, &collection_info)?;
+            COLLECTIONINFO.save(deps.storage, &nft_address, &collection_info)?;
+        }
+        None => {
+            state.referral_bps = bps.unwrap_or_default();
+            assert_all_cuts_fit(deps.storage, &state)?;
+            CONFIG.save(deps.storage,&state)?;
+        }
+    }
+    Ok(Response::new()
+        .add_attribute("action", "set_referral_fee"))
+}
+
+fn execute_set_royalty_source(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    cw2981: bool,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+    collection_info.cw2981_royalties = cw2981;
+    COLLECTIONINFO.save(deps.storage, &nft_address, &collection_info)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_royalty_source")
+        .add_attribute("cw2981", cw2981.to_string()))
+}
+
+fn execute_set_treasury(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let mut state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    state.treasury = Some(deps.api.addr_validate(&address)?.to_string());
+    CONFIG.save(deps.storage,&state)?;
+    Ok(Response::default())
+}
+
+fn execute_withdraw_fees(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    denoms: Option<Vec<String>>,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let treasury = state.treasury.ok_or(ContractError::TreasuryNotSet {})?;
+    let denoms = match denoms {
+        Some(denoms) => denoms,
+        None => ACCRUED_FEES
+            .keys(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?,
+    };
+
+    let mut messages:Vec<CosmosMsg> = vec![];
+    for denom in denoms {
+        let amount = ACCRUED_FEES.may_load(deps.storage, &denom)?.unwrap_or_default();
+        if amount.is_zero() {
+            continue;
+        }
+        ACCRUED_FEES.remove(deps.storage, &denom);
+        messages.push(PaymentAsset {
+            info: payment_info(deps.storage, &denom)?,
+            amount
+        }.into_msg(Addr::unchecked(&treasury))?);
+    }
+    Ok(Response::new()
+        .add_attribute("action", "withdraw_fees")
+        .add_attribute("treasury", treasury)
+        .add_messages(messages))
+}
+
+fn execute_set_pull_payments(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    nft_address: String,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    let state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let mut collection_info = COLLECTIONINFO.may_load(deps.storage, &nft_address)?
+        .ok_or(ContractError::WrongNFTContractError {})?;
+    collection_info.pull_payments = enabled;
+    COLLECTIONINFO.save(deps.storage, &nft_address, &collection_info)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_pull_payments")
+        .add_attribute("enabled", enabled.to_string()))
+}
+
+/// Pays `address` its pending balances. Anyone may trigger it, the funds
+/// always go to `address`.
+fn execute_claim(
+    deps: DepsMut,
+    _env:Env,
+    address: String,
+    assets: Option<Vec<String>>,
+) -> Result<Response, ContractError> {
+    let recipient = deps.api.addr_validate(&address)?;
+    let denoms = match assets {
+        Some(denoms) => denoms,
+        None => PENDING_PAYMENTS
+            .prefix(recipient.as_str())
+            .keys(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()?,
+    };
+
+    let mut messages:Vec<CosmosMsg> = vec![];
+    for denom in denoms {
+        let amount = PENDING_PAYMENTS.may_load(deps.storage, (recipient.as_str(), &denom))?.unwrap_or_default();
+        if amount.is_zero() {
+            continue;
+        }
+        PENDING_PAYMENTS.remove(deps.storage, (recipient.as_str(), &denom));
+        messages.push(PaymentAsset {
+            info: payment_info(deps.storage, &denom)?,
+            amount
+        }.into_msg(recipient.clone())?);
+    }
+    if messages.is_empty() {
+        return Err(ContractError::NothingToClaim {});
+    }
+    Ok(Response::new()
+        .add_attribute("action", "claim")
+        .add_attribute("recipient", recipient)
+        .add_messages(messages))
+}
+
+fn execute_set_tvl(
+    deps: DepsMut,
+    _env:Env,
+    info: MessageInfo,
+    address: String,
+    tvls: Vec<TvlInfo>,
+) -> Result<Response, ContractError> {
+    let  state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    for tvl in tvls{
+        TVL.save(deps.storage, (&address,&tvl.denom), &tvl.amount)?;
+    }
+
+    Ok(Response::default())
+}
+
+fn execute_set_offerings(
+    deps: DepsMut,
+    _env:Env,
+    info:MessageInfo,
+    address: String,
+    offerings:Vec<QueryOfferingsResult>
+) -> Result<Response, ContractError> {
+    let  state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut last_offering_id = 0;
+    for offering in offerings{
+        let crr_offering = Offering{
+            token_id:offering.token_id,
+            seller:offering.seller,
+            list_price:offering.list_price,
+            starts_at:offering.starts_at,
+            expires_at:offering.expires_at,
+            price_decay:offering.price_decay,
+            reserved_for:offering.reserved_for,
+            non_custodial:offering.non_custodial,
+            alt_prices:offering.alt_prices,
+            peg_unit:offering.peg_unit,
+            reserved_root:offering.reserved_root
+        };
+        OFFERINGS.save(deps.storage, (&address,offering.id), &crr_offering)?;
+        last_offering_id = last_offering_id.max(offering.id);
+    }
+
+    // Keep the counter ahead of restored IDs so they are never handed out again.
+    if let Some(mut collection_info) = COLLECTIONINFO.may_load(deps.storage, &address)? {
+        collection_info.last_offering_id = collection_info.last_offering_id.max(last_offering_id);
+        COLLECTIONINFO.save(deps.storage, &address, &collection_info)?;
+    }
+
+    Ok(Response::default())
+}
+
+
+fn execute_history(
+    deps: DepsMut,
+    _env:Env,
+    info:MessageInfo,
+    address: String,
+    histories:Vec<SaleInfo>
+) -> Result<Response, ContractError> {
+    let  state = CONFIG.load(deps.storage)?;
+
+    if info.sender != state.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut count = 0;
+
+    for history in histories{
+        count += 1;
+        SALEHISTORY.save(deps.storage, (&address,&count.to_string()), &history)?;
+    }
+
+    Ok(Response::default())
+}
+
+
+#[entry_point]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let version = get_contract_version(deps.storage)?;
+    if version.contract != CONTRACT_NAME {
+        return Err(ContractError::CannotMigrate { previous_contract: version.contract });
+    }
+
+    // Up to 0.1.x offerings were keyed by a String ID that got reassigned on removal.
+    if version.version.starts_with("0.1.") {
+        migrate_offering_keys(deps.storage)?;
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", version.version))
+}
+
+/// Re-keys String-keyed offerings to `u64` IDs. Numeric IDs are kept as they
+/// are so cached IDs keep pointing at the same listing; anything else gets a
+/// fresh ID. Live counts and `last_offering_id` are rebuilt per collection.
+fn migrate_offering_keys(storage: &mut dyn Storage) -> StdResult<()> {
+    let legacy_offerings = LEGACY_OFFERINGS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut unnumbered:Vec<(String,Offering)> = vec![];
+    for ((nft_address, id), offering) in legacy_offerings {
+        LEGACY_OFFERINGS.remove(storage, (&nft_address, &id));
+        match id.parse::<u64>() {
+            Ok(offering_id) => OFFERINGS.save(storage, (&nft_address, offering_id), &offering)?,
+            Err(_) => unnumbered.push((nft_address, offering)),
+        }
+    }
+
+    for (nft_address, offering) in unnumbered {
+        let offering_id = last_offering_key(storage, &nft_address)? + 1;
+        OFFERINGS.save(storage, (&nft_address, offering_id), &offering)?;
+    }
+
+    let collections = COLLECTIONINFO
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (nft_address, mut collection_info) in collections {
+        collection_info.offering_id = OFFERINGS
+            .prefix(&nft_address)
+            .keys(storage, None, None, Order::Ascending)
+            .count() as u64;
+        collection_info.last_offering_id = collection_info
+            .last_offering_id
+            .max(last_offering_key(storage, &nft_address)?);
+        COLLECTIONINFO.save(storage, &nft_address, &collection_info)?;
+    }
+    Ok(())
+}
+
+fn last_offering_key(storage: &dyn Storage, nft_address: &str) -> StdResult<u64> {
+    Ok(OFFERINGS
+        .prefix(nft_address)
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .unwrap_or_default())
+}
+
+
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetStateInfo {} => to_binary(&query_state_info(deps)?),
+        QueryMsg::GetMembers {address} => to_binary(&query_get_members(deps,address)?),
+        QueryMsg::GetTradingInfo { address} => to_binary(&query_get_trading(deps,address)?),
+        QueryMsg::GetSaleHistory {address, id } => to_binary(&query_get_history(deps,address,id)?),
+        QueryMsg::GetCollectionInfo { address } =>to_binary(&query_collection_info(deps,address)?),
+        QueryMsg::GetOfferingId {address }=> to_binary(&query_get_ids(deps,env,address)?),
+        QueryMsg::GetOfferingPage { id,address }  => to_binary(&query_get_offering(deps,env,id,address)?),
+        QueryMsg::GetTvl { address, symbol }=> to_binary(&query_get_tvl(deps,address,symbol)?),
+        QueryMsg::GetTvlAll { address, symbols }=> to_binary(&query_all_tvl(deps,address,symbols)?),
+        QueryMsg::GetAuctions { address, start_after, limit } => to_binary(&query_get_auctions(deps,address,start_after,limit)?),
+        QueryMsg::GetCurrentPrice { address, offering_id } => to_binary(&query_current_price(deps,env,address,offering_id)?),
+        QueryMsg::GetOffers { address, token_id, start_after, limit } => to_binary(&query_get_offers(deps,address,token_id,start_after,limit)?),
+        QueryMsg::GetCollectionBids { address, start_after, limit } => to_binary(&query_get_collection_bids(deps,address,start_after,limit)?),
+        QueryMsg::GetBundles { start_after, limit } => to_binary(&query_get_bundles(deps,start_after,limit)?),
+        QueryMsg::GetSwaps { start_after, limit } => to_binary(&query_get_swaps(deps,start_after,limit)?),
+        QueryMsg::GetStaleOfferings { address, start_after, limit } => to_binary(&query_get_stale_offerings(deps,env,address,start_after,limit)?),
+        QueryMsg::GetMintTemplates { address, start_after, limit } => to_binary(&query_get_mint_templates(deps,address,start_after,limit)?),
+        QueryMsg::GetLaunch { address } => to_binary(&LAUNCHES.may_load(deps.storage, &address)?),
+        QueryMsg::GetLaunchMinted { address, phase, wallet } => to_binary(&LAUNCH_MINTS.may_load(deps.storage, (&address, phase, &wallet))?.unwrap_or_default()),
+        QueryMsg::GetAccruedFees {} => to_binary(&query_accrued_fees(deps)?),
+        QueryMsg::GetPendingPayments { address } => to_binary(&query_pending_payments(deps,address)?),
+        QueryMsg::GetReferralVolume { referrer } => to_binary(&query_referral_volume(deps,referrer)?)
+    }
+}
+
+pub fn query_state_info(deps:Deps) -> StdResult<State>{
+    let state =  CONFIG.load(deps.storage)?;
+    Ok(state)
+}
+
+pub fn query_accrued_fees(deps:Deps) -> StdResult<Vec<Asset>>{
+    ACCRUED_FEES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Asset { denom, amount }))
+        .collect()
+}
+
+pub fn query_pending_payments(deps:Deps,address:String) -> StdResult<Vec<Asset>>{
+    PENDING_PAYMENTS
+        .prefix(&address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Asset { denom, amount }))
+        .collect()
+}
+
+pub fn query_referral_volume(deps:Deps,referrer:String) -> StdResult<Vec<Asset>>{
+    REFERRAL_VOLUME
+        .prefix(&referrer)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Asset { denom, amount }))
+        .collect()
+}
+
+pub fn query_collection_info(deps:Deps,address:String) -> StdResult<CollectionInfo>{
+    let collection_info =  COLLECTIONINFO.load(deps.storage,&address)?;
+    Ok(collection_info)
+}
+
+
+pub fn query_get_tvl(deps:Deps,address:String,symbol:String) -> StdResult<Uint128>{
+    let tvl = TVL.may_load(deps.storage, (&address,&symbol))?;
+    Ok(tvl.unwrap_or_default())
+}
+
+pub fn query_all_tvl(deps:Deps,address:String,symbols:Vec<String>) -> StdResult<Vec<TvlInfo>>{
+    let mut empty:Vec<TvlInfo> = vec![];
+    for symbol in symbols
+    {
+        let tvl = TVL.may_load(deps.storage, (&address,&symbol))?;
+        empty.push(TvlInfo { denom: symbol, amount: tvl.unwrap_or_default() })
+    }
+    Ok(empty)
+}
+
+
+pub fn query_get_members(deps:Deps,address:String) -> StdResult<Vec<UserInfo>>{
+    let members = MEMBERS.load(deps.storage,&address)?;
+    Ok(members)
+}
+
+pub fn query_get_trading(deps:Deps,address:String) -> StdResult<PriceInfo>{
+    let price_info = PRICEINFO.may_load(deps.storage,&address)?;
+    Ok(price_info.unwrap_or(PriceInfo{
+        total_hope:Uint128::new(0),
+        total_juno:Uint128::new(0)
+    }))
+}
+
+// pub fn query_get_offerings(deps:Deps) -> StdResult<OfferingsResponse>{
+//     let res: StdResult<Vec<QueryOfferingsResult>> = OFFERINGS
+//         .range(deps.storage, None, None, Order::Ascending)
+//         .map(|kv_item| parse_offering(deps, kv_item  ))
+//         .collect();
+//     Ok(OfferingsResponse {
+//         offerings: res?, // Placeholder
+//     })
+// }
+
+// fn parse_offering(
+//     deps:Deps,
+//     item: StdResult<((String,String),Offering)>,
+// ) -> StdResult<QueryOfferingsResult> {
+//     item.and_then(|((address,k), offering)| {
+//         Ok(QueryOfferingsResult {
+//             id: k,
+//             token_id: offering.token_id,
+//             list_price: offering.list_price,
+//             seller: deps.api.addr_validate(&offering.seller)?.to_string(),
+//         })
+//     })
+// }
+
+
+pub fn query_get_ids(deps:Deps,env:Env,address: String) -> StdResult<Vec<u64>>{
+    OFFERINGS
+        .prefix(&address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| item.as_ref().map_or(true, |(_, off)| !is_expired(off, &env) && !off.is_reserved()))
+        .map(|item| item.map(|(id, _)| id))
+        .collect()
+}
+
+
+/// Offerings by explicit ID. Reserved offerings are kept out of
+/// `query_get_ids` but are returned here, for whoever was given the ID.
+pub fn query_get_offering(deps:Deps,env:Env,ids:Vec<u64>,address: String) -> StdResult<Vec<QueryOfferingsResult>>{
+    let mut offering_group:Vec<QueryOfferingsResult> = vec![];
+    for id in ids{
+        match OFFERINGS.may_load(deps.storage,(&address,id))? {
+            Some(offering) if !is_expired(&offering, &env) => {
+                offering_group.push(QueryOfferingsResult{
+                    id,
+                    token_id:offering.token_id,
+                    list_price:offering.list_price,
+                    seller:offering.seller,
+                    starts_at:offering.starts_at,
+                    expires_at:offering.expires_at,
+                    price_decay:offering.price_decay,
+                    reserved_for:offering.reserved_for,
+                    non_custodial:offering.non_custodial,
+                    alt_prices:offering.alt_prices,
+                    peg_unit:offering.peg_unit,
+                    reserved_root:offering.reserved_root
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(offering_group)
+}
+
+pub fn query_current_price(deps:Deps,env:Env,address:String,offering_id:u64) -> StdResult<Asset>{
+    let off = OFFERINGS.load(deps.storage,(&address,offering_id))?;
+    Ok(Asset {
+        amount: offering_price(deps, &env, &off, &off.list_price.denom)?.unwrap_or_default(),
+        denom: off.list_price.denom,
+    })
+}
+
+pub fn query_get_auctions(deps:Deps,address:String,start_after:Option<u64>,limit:Option<u32>) -> StdResult<Vec<QueryAuctionsResult>>{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    AUCTIONS
+        .prefix(&address)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, auction)| QueryAuctionsResult { id, auction }))
+        .collect()
+}
+
+pub fn query_get_offers(deps:Deps,address:String,token_id:String,start_after:Option<u64>,limit:Option<u32>) -> StdResult<Vec<QueryOffersResult>>{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    OFFERS
+        .prefix((&address, &token_id))
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, offer)| QueryOffersResult { id, offer }))
+        .collect()
+}
+
+pub fn query_get_collection_bids(deps:Deps,address:String,start_after:Option<u64>,limit:Option<u32>) -> StdResult<Vec<QueryCollectionBidsResult>>{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    COLLECTION_BIDS
+        .prefix(&address)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, bid)| QueryCollectionBidsResult { id, bid }))
+        .collect()
+}
+
+pub fn query_get_bundles(deps:Deps,start_after:Option<u64>,limit:Option<u32>) -> StdResult<Vec<QueryBundlesResult>>{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    BUNDLES
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, bundle)| QueryBundlesResult { id, bundle }))
+        .collect()
+}
+
+pub fn query_get_swaps(deps:Deps,start_after:Option<u64>,limit:Option<u32>) -> StdResult<Vec<QuerySwapsResult>>{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    SWAPS
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, swap)| QuerySwapsResult { id, swap }))
+        .collect()
+}
+
+/// Ids of approval-based offerings, among the next `limit` offerings, that can
+/// no longer be bought and may be pruned.
+pub fn query_get_stale_offerings(deps:Deps,env:Env,address:String,start_after:Option<u64>,limit:Option<u32>) -> StdResult<Vec<u64>>{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let offerings = OFFERINGS
+        .prefix(&address)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(offerings.into_iter()
+        .filter(|(_, off)| off.non_custodial && !is_listing_approved(deps, &env, &address, &off.token_id, &off.seller))
+        .map(|(id, _)| id)
+        .collect())
+}
+
+pub fn query_get_mint_templates(deps:Deps,address:String,start_after:Option<u64>,limit:Option<u32>) -> StdResult<Vec<QueryMintTemplatesResult>>{
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    MINT_TEMPLATES
+        .prefix(&address)
+        .range(deps.storage, start_after.map(Bound::exclusive), None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(id, template)| QueryMintTemplatesResult { id, template }))
+        .collect()
+}
+
+pub fn query_get_history(deps:Deps,address:String, ids:Vec<String>) -> StdResult<Vec<SaleInfo>>{
+    let mut sale_history : Vec<SaleInfo> = vec![];
+    for id in ids{
+       if let Some(history) = SALEHISTORY.may_load(deps.storage, (&address,&id))? {
+        sale_history.push(history);
+       }
+    }
+    Ok(sale_history)
+}
+
+#[cfg(test)]
+mod tests {
+  
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info, MOCK_CONTRACT_ADDR};
+    use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage};
+    use cosmwasm_std::{ attr, BankMsg, CosmosMsg, Coin, ContractResult, OwnedDeps, SystemError, SystemResult, WasmQuery};
+    use cw20::Cw20ExecuteMsg;
+
+    #[test]
+    fn testing() {
+        //Instantiate
+        let mut deps = mock_dependencies();
+        let instantiate_msg = InstantiateMsg {
+           owner:"creator".to_string()
+        };
+        let info = mock_info("creator", &[]);
+        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
+        assert_eq!(0, res.messages.len());
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.owner,"creator".to_string());
+       
+
+        //Change Owner
+
+        let info = mock_info("creator", &[]);
+        let msg = ExecuteMsg::ChangeOwner { address:"owner".to_string()};
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        let state = query_state_info(deps.as_ref()).unwrap();
+        assert_eq!(state.owner,"owner".to_string());
+
+         //Change Token Contract Address
+
+        let info = mock_info("owner", &[]);
+        let msg = ExecuteMsg::AddTokenAddress  { address:"token_address".to_string(),symbol:"hope".to_string()};
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        
+        let info = mock_info("owner", &[]);
+         let msg = ExecuteMsg::AddTokenAddress  { address:"raw_address".to_string(),symbol:"raw".to_string()};
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+       
+        //Hope1 Collection Add
+       let info = mock_info("owner", &[]);
+       let msg = ExecuteMsg::AddCollection {
+            royalty_portion: Decimal::from_ratio(5u128, 100u128), 
+            members: vec![UserInfo{
+                address:"admin1".to_string(),
+                portion:Decimal::from_ratio(3u128, 10u128)
+                },UserInfo{
+                address:"admin2".to_string(),
+                portion:Decimal::from_ratio(7u128, 10u128)
+                }] ,
+            nft_address: "hope1_address".to_string() ,
+            offering_id:0,
+            sale_id:0
+        };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+       
+
+       // Sell nft
+        let cw721_msg = SellNft{
+            list_price:Asset{
+                denom:"ujuno".to_string(),
+                amount:Uint128::new(1000000)
+            },
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            alt_prices:None,
+            peg_unit:None,
+            reserved_root:None
+        };
+
+        let info = mock_info("hope1_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+            sender:"owner1".to_string(),
+            token_id:"Hope.1".to_string(),
+            msg:to_binary(&cw721_msg).unwrap()
+        });
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+
+        let collection_info = query_collection_info(deps.as_ref(),
+                "hope1_address".to_string()).unwrap();
+        assert_eq!(collection_info,CollectionInfo{
+            nft_address:"hope1_address".to_string(),
+            offering_id:1,
+            royalty_portion:Decimal::from_ratio(5u128, 100u128),
+            sale_id:0,
+            last_offering_id:1,
+            last_auction_id:0,
+            last_offer_id:0,
+            last_collection_bid_id:0,
+            last_mint_template_id:0,
+            protocol_fee:None,
+            pull_payments:false,
+            referral_bps:None,
+            cw2981_royalties:false
+            });
+
+      
+        let offerings = query_get_offering(deps.as_ref(),mock_env(),vec![1,2],"hope1_address".to_string()).unwrap();
+        assert_eq!(offerings,vec![QueryOfferingsResult{
+            id:1,
+            token_id:"Hope.1".to_string(),
+            list_price:Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000000) },
+            seller:"owner1".to_string(),
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            non_custodial:false,
+            alt_prices:vec![],
+            peg_unit:None,
+            reserved_root:None
+        }]);
+
+            //Buy nft
+
+      let info = mock_info("test_buyer1", &[Coin{
+        denom:"ujuno".to_string(),
+        amount:Uint128::new(1000000)
+      }]);
+      let msg = ExecuteMsg::BuyNft { offering_id: 1, nft_address: "hope1_address".to_string(), token_id: Some("Hope.1".to_string()), max_price: None, proof: None, referrer: None };
+      let res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+      assert_eq!(res.messages.len(),4);
+
+       let collection_info = query_collection_info(deps.as_ref(),"hope1_address".to_string()).unwrap();
+       assert_eq!(collection_info.offering_id,0); 
+
+      assert_eq!(res.messages[0].msg,CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "hope1_address".to_string(),
+                funds: vec![],
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: "test_buyer1".to_string(),
+                    token_id:"Hope.1".to_string(),
+            }).unwrap(),
+        }));
+
+      assert_eq!(res.messages[1].msg,CosmosMsg::Bank(BankMsg::Send {
+                to_address: "owner1".to_string(),
+                amount:vec![Coin{
+                    denom:"ujuno".to_string(),
+                    amount:Uint128::new(950000)
+                }]
+        }));
+
+        assert_eq!(res.messages[2].msg,CosmosMsg::Bank(BankMsg::Send {
+                to_address: "admin1".to_string(),
+                amount:vec![Coin{
+                    denom:"ujuno".to_string(),
+                    amount:Uint128::new(15000)
+                }]
+        }));
+        assert_eq!(res.messages[3].msg,CosmosMsg::Bank(BankMsg::Send {
+                to_address: "admin2".to_string(),
+                amount:vec![Coin{
+                    denom:"ujuno".to_string(),
+                    amount:Uint128::new(35000)
+                }]
+        }));
+                                                                    
+        let ids =  query_get_ids(deps.as_ref(),mock_env(),"hope1_address".to_string()).unwrap();
+        let test_id:Vec<u64> = vec![];
+        assert_eq!(ids,test_id);
+        
+
+         // Offering IDs are never reused or reshuffled
+
+         //sell
+        let cw721_msg = SellNft{
+            list_price:Asset{
+                denom:"osmos".to_string(),
+                amount:Uint128::new(2000000)
+            },
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            alt_prices:None,
+            peg_unit:None,
+            reserved_root:None
+        };
+
+        let info = mock_info("hope1_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+            sender:"buyer1".to_string(),
+            token_id:"Hope.1".to_string(),
+            msg:to_binary(&cw721_msg).unwrap()
+        });
+         execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+     
+         //sell
+         let cw721_msg = SellNft{
+            list_price:Asset{
+                denom:"ujuno".to_string(),
+                amount:Uint128::new(2000000)
+            },
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            alt_prices:None,
+            peg_unit:None,
+            reserved_root:None
+        };
+
+          let info = mock_info("hope1_address", &[]);
+          let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+            sender:"buyer2".to_string(),
+            token_id:"Hope.2".to_string(),
+            msg:to_binary(&cw721_msg).unwrap()
+            });
+         execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+         //buy
+
+        let info = mock_info("test_buyer2", &[Coin{
+            denom:"osmos".to_string(),
+            amount:Uint128::new(2000000)
+        }]);
+        let msg = ExecuteMsg::BuyNft { offering_id: 2, nft_address: "hope1_address".to_string(), token_id: None, max_price: Some(Uint128::new(2000000)), proof: None, referrer: None };
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        let id = query_get_ids(deps.as_ref(),mock_env(), "hope1_address".to_string()).unwrap();
+        let collection_info = query_collection_info(deps.as_ref(),"hope1_address".to_string()).unwrap();
+        assert_eq!(collection_info.offering_id,1);
+        assert_eq!(collection_info.last_offering_id,3);
+        assert_eq!(id,vec![3]);
+
+         let cw721_msg = SellNft{
+            list_price:Asset{
+                denom:"hope".to_string(),
+                amount:Uint128::new(2000000)
+            },
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            alt_prices:None,
+            peg_unit:None,
+            reserved_root:None
+        };
+
+        let info = mock_info("hope1_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+            sender:"buyer3".to_string(),
+            token_id:"Hope.3".to_string(),
+            msg:to_binary(&cw721_msg).unwrap()
+        });
+
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        let ids = query_get_ids(deps.as_ref(),mock_env(),"hope1_address".to_string()).unwrap();
+        assert_eq!(ids,vec![3,4]);
+        let offerings = query_get_offering(deps.as_ref(),mock_env(),vec![3,4],"hope1_address".to_string()).unwrap();
+        assert_eq!(offerings,vec![QueryOfferingsResult{
+            id:3,
+            token_id:"Hope.2".to_string(),
+            list_price:Asset { denom: "ujuno".to_string(),  amount:Uint128::new(2000000) },
+            seller:"buyer2".to_string(),
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            non_custodial:false,
+            alt_prices:vec![],
+            peg_unit:None,
+            reserved_root:None
+        },QueryOfferingsResult{
+            id:4,
+            token_id:"Hope.3".to_string(),
+            list_price:Asset { denom: "hope".to_string(),  amount:Uint128::new(2000000) },
+            seller:"buyer3".to_string(),
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            non_custodial:false,
+            alt_prices:vec![],
+            peg_unit:None,
+            reserved_root:None
+        }]);
+
+        let cw20_msg= BuyNft{
+            offering_id:4,
+            nft_address:"hope1_address".to_string(),
+            token_id:Some("Hope.3".to_string()),
+            max_price:None,
+            proof:None,
+            referrer:None
+        };
+
+        let info = mock_info("token_address", &[]);
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"test_buyer3".to_string(),
+            amount:Uint128::new(2000000),
+            msg:to_binary(&cw20_msg).unwrap()
+        });
+        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();    
+        assert_eq!(res.messages[1].msg,CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token_address".to_string(),
+                funds: vec![],
+                msg: to_binary(&Cw20ExecuteMsg::Transfer { 
+                    recipient: "buyer3".to_string(), 
+                    amount:Uint128::new(1900000),
+                 }).unwrap()
+         }));
+
+         assert_eq!(res.messages[2].msg,CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token_address".to_string(),
+                funds: vec![],
+                msg: to_binary(&Cw20ExecuteMsg::Transfer { 
+                    recipient: "admin1".to_string(), 
+                    amount:Uint128::new(30000),
+                 }).unwrap()
+         }));
+
+          assert_eq!(res.messages[3].msg,CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token_address".to_string(),
+                funds: vec![],
+                msg: to_binary(&Cw20ExecuteMsg::Transfer { 
+                    recipient: "admin2".to_string(), 
+                    amount:Uint128::new(70000),
+                 }).unwrap()
+         }));
+
+
+        let offerings = query_get_offering(deps.as_ref(),mock_env(),vec![3,4],"hope1_address".to_string()).unwrap();
+        assert_eq!(offerings,vec![QueryOfferingsResult{
+            id:3,
+            token_id:"Hope.2".to_string(),
+            list_price:Asset { denom: "ujuno".to_string(),  amount:Uint128::new(2000000) },
+            seller:"buyer2".to_string(),
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            non_custodial:false,
+            alt_prices:vec![],
+            peg_unit:None,
+            reserved_root:None
+        }]);
+
+        let cw721_msg = SellNft{
+            list_price:Asset{
+                denom:"raw".to_string(),
+                amount:Uint128::new(2000000)
+            },
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            alt_prices:None,
+            peg_unit:None,
+            reserved_root:None
+        };
+
+        let info = mock_info("hope1_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+            sender:"owner1".to_string(),
+            token_id:"Hope.1".to_string(),
+            msg:to_binary(&cw721_msg).unwrap()
+        });
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        let cw20_msg= BuyNft{
+            offering_id:5,
+            nft_address:"hope1_address".to_string(),
+            token_id:None,
+            max_price:None,
+            proof:None,
+            referrer:None
+        };
+
+        let info = mock_info("raw_address", &[]);
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"test_buyer4".to_string(),
+            amount:Uint128::new(2000000),
+            msg:to_binary(&cw20_msg).unwrap()
+        });
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
 
-pub fn query_get_offering(deps:Deps,ids:Vec<String>,address: String) -> StdResult<Vec<QueryOfferingsResult>>{
-    let mut offering_group:Vec<QueryOfferingsResult> = vec![];
-    for id in ids{
-        let offering = OFFERINGS.may_load(deps.storage,(&address,&id))?;
-        if offering!=None{
-            let offering = offering.unwrap();
-            offering_group.push(QueryOfferingsResult{
-                id,
-                token_id:offering.token_id,
-                list_price:offering.list_price,
-                seller:offering.seller
-            });
-        }
-    }
-    Ok(offering_group)
-}
+        assert_eq!(offerings,vec![QueryOfferingsResult{
+            id:3,
+            token_id:"Hope.2".to_string(),
+            list_price:Asset { denom: "ujuno".to_string(),  amount:Uint128::new(2000000) },
+            seller:"buyer2".to_string(),
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            non_custodial:false,
+            alt_prices:vec![],
+            peg_unit:None,
+            reserved_root:None
+        }]);
 
-pub fn query_get_history(deps:Deps,address:String, ids:Vec<String>) -> StdResult<Vec<SaleInfo>>{
-    let mut sale_history : Vec<SaleInfo> = vec![];
-    for id in ids{
-       let history =  SALEHISTORY.may_load(deps.storage, (&address,&id))?;
-       if history != None{
-        sale_history.push(history.unwrap());
-       }
-    }
-    Ok(sale_history)
-}
+        let juno_tvl = query_get_tvl(deps.as_ref(),"hope1_address".to_string(),"ujuno".to_string()).unwrap();
+        let hope_tvl = query_get_tvl(deps.as_ref(),"hope1_address".to_string(),"hope".to_string()).unwrap();
+        let osmos_tvl = query_get_tvl(deps.as_ref(),"hope1_address".to_string(),"osmos".to_string()).unwrap();
+        let raw_tvl  = query_get_tvl(deps.as_ref(),"hope1_address".to_string(),"raw".to_string()).unwrap();
+        assert_eq!(juno_tvl,Uint128::new(1000000));
+        assert_eq!(hope_tvl,Uint128::new(2000000));
+        assert_eq!(osmos_tvl,Uint128::new(2000000));
+        assert_eq!(raw_tvl,Uint128::new(2000000));
 
-#[cfg(test)]
-mod tests {
-  
-    use super::*;
-    use crate::state::Asset;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-    use cosmwasm_std::{ CosmosMsg, Coin};
+        let collection_info = query_collection_info(deps.as_ref(), "hope1_address".to_string()).unwrap();
+        assert_eq!(collection_info.sale_id,4);
+        let _sale_history = query_get_history(deps.as_ref(), "hope1_address".to_string(), vec!["1".to_string(),"2".to_string(),"3".to_string(),"4".to_string()]).unwrap();
+        
+        let tvl_all = query_all_tvl(deps.as_ref(), "hope1_address".to_string(), vec!["ujuno".to_string(),"hope".to_string(),"osmos".to_string(),"xyz".to_string()]).unwrap();
+        assert_eq!(tvl_all,vec![TvlInfo{
+            denom:"ujuno".to_string(),
+            amount:Uint128::new(1000000)
+        },TvlInfo{
+            denom:"hope".to_string(),
+            amount:Uint128::new(2000000)
+        },TvlInfo{
+            denom:"osmos".to_string(),
+            amount:Uint128::new(2000000)
+        },TvlInfo{
+            denom:"xyz".to_string(),
+            amount:Uint128::new(0)
+        }]);
+
+         let info = mock_info("owner", &[]);
+        let msg = ExecuteMsg::SetTvl { address: "hope1_address".to_string(), tvl: vec![TvlInfo{
+            denom:"ujuno".to_string(),
+            amount:Uint128::new(0)
+        }] };
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        let tvl_all = query_all_tvl(deps.as_ref(), "hope1_address".to_string(), vec!["ujuno".to_string()]).unwrap();
+        assert_eq!(tvl_all,vec![TvlInfo{
+            denom:"ujuno".to_string(),
+            amount:Uint128::new(0)
+        }]);
+       
+    }
 
     #[test]
-    fn testing() {
-        //Instantiate
+    fn migrate_rekeys_legacy_offerings() {
         let mut deps = mock_dependencies();
-        let instantiate_msg = InstantiateMsg {
-           owner:"creator".to_string()
-        };
-        let info = mock_info("creator", &[]);
-        let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-        assert_eq!(0, res.messages.len());
-        let state = query_state_info(deps.as_ref()).unwrap();
-        assert_eq!(state.owner,"creator".to_string());
-       
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.1.0").unwrap();
+        // Stored as 0.1.x wrote them, before any of the serde(default) fields
+        deps.as_mut().storage.set(&COLLECTIONINFO.key("hope1_address"),
+            br#"{"nft_address":"hope1_address","offering_id":2,"royalty_portion":"0.05","sale_id":7}"#);
+        let offering = |token_id:&str| format!(
+            r#"{{"token_id":"{}","seller":"owner1","list_price":{{"denom":"ujuno","amount":"1000000"}}}}"#, token_id);
+        deps.as_mut().storage.set(&LEGACY_OFFERINGS.key(("hope1_address","1")), offering("Hope.1").as_bytes());
+        deps.as_mut().storage.set(&LEGACY_OFFERINGS.key(("hope1_address","2")), offering("Hope.2").as_bytes());
+        deps.as_mut().storage.set(&LEGACY_OFFERINGS.key(("hope1_address","restored")), offering("Hope.3").as_bytes());
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+        let ids = query_get_ids(deps.as_ref(),mock_env(), "hope1_address".to_string()).unwrap();
+        assert_eq!(ids,vec![1,2,3]);
+        assert_eq!(OFFERINGS.load(deps.as_ref().storage, ("hope1_address",3)).unwrap(), Offering{
+            token_id:"Hope.3".to_string(),
+            seller:"owner1".to_string(),
+            list_price:Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000000) },
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            non_custodial:false,
+            alt_prices:vec![],
+            peg_unit:None,
+            reserved_root:None
+        });
+        let offerings = query_get_offering(deps.as_ref(),mock_env(), vec![1,2,3], "hope1_address".to_string()).unwrap();
+        assert_eq!(offerings.iter().map(|o|o.token_id.clone()).collect::<Vec<String>>(),
+            vec!["Hope.1".to_string(),"Hope.2".to_string(),"Hope.3".to_string()]);
 
-        //Change Owner
+        let collection_info = query_collection_info(deps.as_ref(), "hope1_address".to_string()).unwrap();
+        assert_eq!(collection_info.offering_id,3);
+        assert_eq!(collection_info.last_offering_id,3);
+        assert_eq!(collection_info.sale_id,7);
+        assert_eq!(get_contract_version(deps.as_ref().storage).unwrap().version,CONTRACT_VERSION);
 
-        let info = mock_info("creator", &[]);
-        let msg = ExecuteMsg::ChangeOwner { address:"owner".to_string()};
+        // A new listing continues after the migrated IDs
+        let cw721_msg = SellNft{
+            list_price:Asset{
+                denom:"ujuno".to_string(),
+                amount:Uint128::new(1000000)
+            },
+            starts_at:None,
+            expires_at:None,
+            price_decay:None,
+            reserved_for:None,
+            alt_prices:None,
+            peg_unit:None,
+            reserved_root:None
+        };
+        let info = mock_info("hope1_address", &[]);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+            sender:"owner2".to_string(),
+            token_id:"Hope.4".to_string(),
+            msg:to_binary(&cw721_msg).unwrap()
+        });
         execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        let state = query_state_info(deps.as_ref()).unwrap();
-        assert_eq!(state.owner,"owner".to_string());
+        let ids = query_get_ids(deps.as_ref(),mock_env(), "hope1_address".to_string()).unwrap();
+        assert_eq!(ids,vec![1,2,3,4]);
 
-         //Change Token Contract Address
+        let mut deps = mock_dependencies();
+        set_contract_version(deps.as_mut().storage, "other_contract", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert!(matches!(err, ContractError::CannotMigrate { .. }));
+    }
 
-        let info = mock_info("owner", &[]);
-        let msg = ExecuteMsg::AddTokenAddress  { address:"token_address".to_string(),symbol:"hope".to_string()};
-        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-        
-        let info = mock_info("owner", &[]);
-         let msg = ExecuteMsg::AddTokenAddress  { address:"raw_address".to_string(),symbol:"raw".to_string()};
-        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-       
-        //Hope1 Collection Add
-       let info = mock_info("owner", &[]);
-       let msg = ExecuteMsg::AddCollection {
-            royalty_portion: Decimal::from_ratio(5 as u128, 100 as u128), 
+    fn setup_contract(deps: DepsMut) {
+        instantiate(deps, mock_env(), mock_info("creator", &[]), InstantiateMsg {
+           owner:"owner".to_string()
+        }).unwrap();
+    }
+
+    fn add_collection(deps: DepsMut, nft_address: &str) {
+        let msg = ExecuteMsg::AddCollection {
+            royalty_portion: Decimal::from_ratio(5u128, 100u128),
             members: vec![UserInfo{
                 address:"admin1".to_string(),
-                portion:Decimal::from_ratio(3 as u128, 10 as u128)
+                portion:Decimal::from_ratio(3u128, 10u128)
                 },UserInfo{
                 address:"admin2".to_string(),
-                portion:Decimal::from_ratio(7 as u128, 10 as u128)
+                portion:Decimal::from_ratio(7u128, 10u128)
                 }] ,
-            nft_address: "hope1_address".to_string() ,
+            nft_address: nft_address.to_string() ,
             offering_id:0,
             sale_id:0
         };
-        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
-       
+        execute(deps, mock_env(), mock_info("owner", &[]), msg).unwrap();
+    }
 
-       // Sell nft
-        let cw721_msg = SellNft{
-            list_price:Asset{
-                denom:"ujuno".to_string(),
-                amount:Uint128::new(1000000)
-            }
-        };
+    fn sell_nft(deps: DepsMut, nft_address: &str, seller: &str, token_id: &str, list_price: Asset) {
+        list_nft(deps, nft_address, seller, token_id,
+            &SellNft{ list_price, starts_at: None, expires_at: None, price_decay: None, reserved_for: None, alt_prices: None, peg_unit: None, reserved_root: None }).unwrap();
+    }
 
-        let info = mock_info("hope1_address", &[]);
+    fn list_nft(deps: DepsMut, nft_address: &str, seller: &str, token_id: &str, msg: &SellNft) -> Result<Response, ContractError> {
         let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
-            sender:"owner1".to_string(),
-            token_id:"Hope.1".to_string(),
-            msg:to_binary(&cw721_msg).unwrap()
+            sender:seller.to_string(),
+            token_id:token_id.to_string(),
+            msg:to_binary(msg).unwrap()
         });
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        execute(deps, mock_env(), mock_info(nft_address, &[]), msg)
+    }
 
+    #[test]
+    fn buy_guard_rejects_changed_offering() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+        sell_nft(deps.as_mut(), "hope1_address", "owner1", "Hope.1",
+            Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000000) });
+        sell_nft(deps.as_mut(), "hope1_address", "owner2", "Hope.2",
+            Asset { denom: "hope".to_string(), amount: Uint128::new(1000000) });
+
+        let funds = [Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000000) }];
+
+        // Wrong token
+        let msg = ExecuteMsg::BuyNft { offering_id: 1, nft_address: "hope1_address".to_string(),
+            token_id: Some("Hope.2".to_string()), max_price: None, proof: None, referrer: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &funds), msg).unwrap_err();
+        assert!(matches!(err, ContractError::OfferingMismatch {}));
+
+        // Price above what the buyer accepts
+        let msg = ExecuteMsg::BuyNft { offering_id: 1, nft_address: "hope1_address".to_string(),
+            token_id: Some("Hope.1".to_string()), max_price: Some(Uint128::new(999999)), proof: None, referrer: None };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &funds), msg).unwrap_err();
+        assert!(matches!(err, ContractError::OfferingMismatch {}));
+
+        // cw20 hook is guarded the same way
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"buyer".to_string(),
+            amount:Uint128::new(1000000),
+            msg:to_binary(&BuyNft{
+                offering_id:2,
+                nft_address:"hope1_address".to_string(),
+                token_id:Some("Hope.1".to_string()),
+                max_price:None,
+                proof:None,
+                referrer:None
+            }).unwrap()
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("token_address", &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::OfferingMismatch {}));
 
-        let collection_info = query_collection_info(deps.as_ref(),
-                "hope1_address".to_string()).unwrap();
-        assert_eq!(collection_info,CollectionInfo{
-            nft_address:"hope1_address".to_string(),
-            offering_id:1,
-            royalty_portion:Decimal::from_ratio(5 as u128, 100 as u128),
-            sale_id:0
+        let msg = ExecuteMsg::BuyNft { offering_id: 1, nft_address: "hope1_address".to_string(),
+            token_id: Some("Hope.1".to_string()), max_price: Some(Uint128::new(1000000)), proof: None, referrer: None };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &funds), msg).unwrap();
+        assert_eq!(res.messages.len(),4);
+    }
+
+    #[test]
+    fn update_price() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        sell_nft(deps.as_mut(), "hope1_address", "owner1", "Hope.1",
+            Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000000) });
+
+        let update = |list_price: Asset| ExecuteMsg::UpdatePrice {
+            nft_address: "hope1_address".to_string(),
+            offering_id: 1,
+            list_price
+        };
+        let new_price = Asset { denom: "ujuno".to_string(), amount: Uint128::new(2000000) };
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("someone", &[]), update(new_price.clone())).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner1", &[]),
+            update(Asset { denom: "ujuno".to_string(), amount: Uint128::zero() })).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPrice {}));
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner1", &[]), update(new_price.clone())).unwrap();
+        assert!(res.attributes.contains(&attr("old_price", "1000000ujuno")));
+        assert!(res.attributes.contains(&attr("new_price", "2000000ujuno")));
+
+        let offerings = query_get_offering(deps.as_ref(),mock_env(), vec![1], "hope1_address".to_string()).unwrap();
+        assert_eq!(offerings[0].list_price, new_price);
+        assert_eq!(query_get_ids(deps.as_ref(),mock_env(), "hope1_address".to_string()).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn listing_schedule_and_prune() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        let now = mock_env().block.time.seconds();
+        let price = Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000000) };
+
+        let list = |deps: DepsMut, seller: &str, token_id: &str, starts_at: Option<u64>, expires_at: Option<u64>| {
+            let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+                sender:seller.to_string(),
+                token_id:token_id.to_string(),
+                msg:to_binary(&SellNft{ list_price: price.clone(), starts_at, expires_at, price_decay: None, reserved_for: None, alt_prices: None, peg_unit: None, reserved_root: None }).unwrap()
             });
+            execute(deps, mock_env(), mock_info("hope1_address", &[]), msg)
+        };
 
-      
-        let offerings = query_get_offering(deps.as_ref(),vec!["1".to_string(),"2".to_string()],"hope1_address".to_string()).unwrap();
-        assert_eq!(offerings,vec![QueryOfferingsResult{
-            id:"1".to_string(),
-            token_id:"Hope.1".to_string(),
-            list_price:Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000000) },
-            seller:"owner1".to_string()
-        }]);
+        let err = list(deps.as_mut(), "owner1", "Hope.0", None, Some(now)).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidListingTime {}));
+        let err = list(deps.as_mut(), "owner1", "Hope.0", Some(now + 200), Some(now + 100)).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidListingTime {}));
+
+        list(deps.as_mut(), "owner1", "Hope.1", Some(now + 100), Some(now + 1000)).unwrap();
+        list(deps.as_mut(), "owner2", "Hope.2", None, Some(now + 10)).unwrap();
+        list(deps.as_mut(), "owner3", "Hope.3", None, None).unwrap();
+
+        let funds = [Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000000) }];
+        let buy = |offering_id: u64| ExecuteMsg::BuyNft { offering_id, nft_address: "hope1_address".to_string(),
+            token_id: None, max_price: None, proof: None, referrer: None };
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &funds), buy(1)).unwrap_err();
+        assert!(matches!(err, ContractError::ListingNotStarted {}));
+
+        let mut later = mock_env();
+        later.block.time = later.block.time.plus_seconds(100);
+
+        let err = execute(deps.as_mut(), later.clone(), mock_info("buyer", &funds), buy(2)).unwrap_err();
+        assert!(matches!(err, ContractError::ListingExpired {}));
+
+        let ids = query_get_ids(deps.as_ref(), later.clone(), "hope1_address".to_string()).unwrap();
+        assert_eq!(ids, vec![1,3]);
+        let offerings = query_get_offering(deps.as_ref(), later.clone(), vec![1,2,3], "hope1_address".to_string()).unwrap();
+        assert_eq!(offerings.len(), 2);
+
+        // Nothing has expired yet at listing time
+        let res = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]),
+            ExecuteMsg::PruneExpired { nft_address: "hope1_address".to_string(), start_after: None, limit: None }).unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        // The scan itself is bounded, so paging past the expired one finds nothing
+        let res = execute(deps.as_mut(), later.clone(), mock_info("anyone", &[]),
+            ExecuteMsg::PruneExpired { nft_address: "hope1_address".to_string(), start_after: None, limit: Some(1) }).unwrap();
+        assert_eq!(res.messages.len(), 0);
+        assert!(res.attributes.contains(&attr("last_scanned", "1")));
+        let res = execute(deps.as_mut(), later.clone(), mock_info("anyone", &[]),
+            ExecuteMsg::PruneExpired { nft_address: "hope1_address".to_string(), start_after: Some(2), limit: None }).unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        let res = execute(deps.as_mut(), later.clone(), mock_info("anyone", &[]),
+            ExecuteMsg::PruneExpired { nft_address: "hope1_address".to_string(), start_after: None, limit: None }).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "hope1_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: "owner2".to_string(),
+                token_id: "Hope.2".to_string(),
+            }).unwrap(),
+        }));
 
-            //Buy nft
+        let collection_info = query_collection_info(deps.as_ref(), "hope1_address".to_string()).unwrap();
+        assert_eq!(collection_info.offering_id, 2);
+        assert!(OFFERINGS.may_load(deps.as_ref().storage, ("hope1_address", 2)).unwrap().is_none());
 
-      let info = mock_info("test_buyer1", &[Coin{
-        denom:"ujuno".to_string(),
-        amount:Uint128::new(1000000)
-      }]);
-      let msg = ExecuteMsg::BuyNft { offering_id: "1".to_string(), nft_address: "hope1_address".to_string() };
-      let res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
-      assert_eq!(res.messages.len(),4);
+        let res = execute(deps.as_mut(), later.clone(), mock_info("buyer", &funds), buy(1)).unwrap();
+        assert_eq!(res.messages.len(), 4);
+    }
 
-       let collection_info = query_collection_info(deps.as_ref(),"hope1_address".to_string()).unwrap();
-       assert_eq!(collection_info.offering_id,0); 
+    #[test]
+    fn english_auction() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+        let start = mock_env().block.time.seconds();
+        let at = |seconds: u64| {
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(seconds);
+            env
+        };
 
-      assert_eq!(res.messages[0].msg,CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: "hope1_address".to_string(),
-                funds: vec![],
-                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                    recipient: "test_buyer1".to_string(),
-                    token_id:"Hope.1".to_string(),
+        let start_auction = |deps: DepsMut, token_id: &str, denom: &str| {
+            let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+                sender:"seller".to_string(),
+                token_id:token_id.to_string(),
+                msg:to_binary(&Cw721HookMsg::StartAuction(StartAuction {
+                    reserve_price: Asset { denom: denom.to_string(), amount: Uint128::new(1000) },
+                    duration: 1000,
+                    min_bid_increment: Uint128::new(100),
+                    extension_window: 300,
+                })).unwrap()
+            });
+            execute(deps, mock_env(), mock_info("hope1_address", &[]), msg).unwrap();
+        };
+        // Durations that overflow or windows longer than the auction are refused
+        for (duration, extension_window) in [(u64::MAX, 300), (1000, 1001)] {
+            let err = execute(deps.as_mut(), mock_env(), mock_info("hope1_address", &[]), ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+                sender:"seller".to_string(),
+                token_id:"Hope.1".to_string(),
+                msg:to_binary(&Cw721HookMsg::StartAuction(StartAuction {
+                    reserve_price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) },
+                    duration,
+                    min_bid_increment: Uint128::new(100),
+                    extension_window,
+                })).unwrap()
+            })).unwrap_err();
+            assert!(matches!(err, ContractError::InvalidListingTime {}));
+        }
+        start_auction(deps.as_mut(), "Hope.1", "ujuno");
+        start_auction(deps.as_mut(), "Hope.2", "hope");
+
+        let bid = |deps: DepsMut, env: Env, bidder: &str, amount: u128| {
+            execute(deps, env, mock_info(bidder, &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(amount) }]),
+                ExecuteMsg::PlaceBid { nft_address: "hope1_address".to_string(), auction_id: 1 })
+        };
+
+        let err = bid(deps.as_mut(), mock_env(), "bidder1", 999).unwrap_err();
+        assert!(matches!(err, ContractError::BidTooLow { .. }));
+        let res = bid(deps.as_mut(), mock_env(), "bidder1", 1000).unwrap();
+        assert_eq!(res.messages.len(), 0);
+
+        let err = bid(deps.as_mut(), mock_env(), "bidder2", 1099).unwrap_err();
+        assert!(matches!(err, ContractError::BidTooLow { min_bid } if min_bid == Uint128::new(1100)));
+
+        // Outbidding refunds the previous bidder; a late bid extends the auction
+        let res = bid(deps.as_mut(), at(900), "bidder2", 1100).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "bidder1".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]
+        }));
+        let auctions = query_get_auctions(deps.as_ref(), "hope1_address".to_string(), None, None).unwrap();
+        assert_eq!(auctions[0].auction.end_time, start + 1200);
+
+        let err = execute(deps.as_mut(), at(1000), mock_info("anyone", &[]),
+            ExecuteMsg::SettleAuction { nft_address: "hope1_address".to_string(), auction_id: 1 }).unwrap_err();
+        assert!(matches!(err, ContractError::AuctionNotEnded {}));
+        let err = execute(deps.as_mut(), at(1000), mock_info("seller", &[]),
+            ExecuteMsg::CancelAuction { nft_address: "hope1_address".to_string(), auction_id: 1 }).unwrap_err();
+        assert!(matches!(err, ContractError::AuctionHasBids {}));
+
+        let err = bid(deps.as_mut(), at(1200), "bidder1", 5000).unwrap_err();
+        assert!(matches!(err, ContractError::AuctionEnded {}));
+
+        let res = execute(deps.as_mut(), at(1200), mock_info("anyone", &[]),
+            ExecuteMsg::SettleAuction { nft_address: "hope1_address".to_string(), auction_id: 1 }).unwrap();
+        assert_eq!(res.messages.len(), 4);
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "hope1_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: "bidder2".to_string(),
+                token_id: "Hope.1".to_string(),
+            }).unwrap(),
+        }));
+        assert_eq!(res.messages[1].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "seller".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1045) }]
+        }));
+        assert_eq!(res.messages[3].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "admin2".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(39) }]
+        }));
+        let collection_info = query_collection_info(deps.as_ref(), "hope1_address".to_string()).unwrap();
+        assert_eq!(collection_info.sale_id, 1);
+
+        // cw20 bids go through the Receive hook
+        let cw20_bid = |deps: DepsMut, bidder: &str, amount: u128| {
+            execute(deps, mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+                sender:bidder.to_string(),
+                amount:Uint128::new(amount),
+                msg:to_binary(&Cw20HookMsg::PlaceBid { nft_address: "hope1_address".to_string(), auction_id: 2 }).unwrap()
+            }))
+        };
+        cw20_bid(deps.as_mut(), "bidder1", 1000).unwrap();
+        let res = cw20_bid(deps.as_mut(), "bidder2", 2000).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "bidder1".to_string(),
+                amount: Uint128::new(1000),
+            }).unwrap()
+        }));
+        let res = execute(deps.as_mut(), at(1000), mock_info("anyone", &[]),
+            ExecuteMsg::SettleAuction { nft_address: "hope1_address".to_string(), auction_id: 2 }).unwrap();
+        assert_eq!(res.messages[1].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "seller".to_string(),
+                amount: Uint128::new(1900),
+            }).unwrap()
+        }));
+        assert_eq!(query_get_trading(deps.as_ref(), "hope1_address".to_string()).unwrap(), PriceInfo {
+            total_juno: Uint128::new(1100),
+            total_hope: Uint128::new(2000),
+        });
+        assert!(query_get_auctions(deps.as_ref(), "hope1_address".to_string(), None, None).unwrap().is_empty());
+
+        // Without bids the seller can take the NFT back
+        start_auction(deps.as_mut(), "Hope.3", "ujuno");
+        let err = execute(deps.as_mut(), mock_env(), mock_info("bidder1", &[]),
+            ExecuteMsg::CancelAuction { nft_address: "hope1_address".to_string(), auction_id: 3 }).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("seller", &[]),
+            ExecuteMsg::CancelAuction { nft_address: "hope1_address".to_string(), auction_id: 3 }).unwrap();
+        assert_eq!(res.messages.len(), 1);
+    }
+
+    #[test]
+    fn dutch_auction_price_decay() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        let start = mock_env().block.time.seconds();
+        let at = |seconds: u64| {
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(seconds);
+            env
+        };
+        let dutch = |end_price: u128, step_seconds: Option<u64>| SellNft {
+            list_price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(10000) },
+            starts_at: None,
+            expires_at: None,
+            price_decay: Some(PriceDecay {
+                end_price: Uint128::new(end_price),
+                start_time: start + 100,
+                end_time: start + 1100,
+                step_seconds,
+            }),
+            reserved_for: None,
+            alt_prices: None,
+            peg_unit: None,
+            reserved_root: None,
+        };
+
+        let err = list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.0", &dutch(10000, None)).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPrice {}));
+        list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", &dutch(2000, None)).unwrap();
+        list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.2", &dutch(2000, Some(300))).unwrap();
+
+        let price = |deps: Deps, seconds: u64, offering_id: u64|
+            query_current_price(deps, at(seconds), "hope1_address".to_string(), offering_id).unwrap().amount;
+        assert_eq!(price(deps.as_ref(), 0, 1), Uint128::new(10000));
+        assert_eq!(price(deps.as_ref(), 350, 1), Uint128::new(8000));
+        assert_eq!(price(deps.as_ref(), 5000, 1), Uint128::new(2000));
+        // Stepwise: still at the first step after 250 seconds of decay
+        assert_eq!(price(deps.as_ref(), 350, 2), Uint128::new(10000));
+        assert_eq!(price(deps.as_ref(), 400, 2), Uint128::new(7600));
+
+        // Paying more than the live price refunds the difference
+        let funds = [Coin{ denom:"ujuno".to_string(), amount:Uint128::new(9000) }];
+        let msg = ExecuteMsg::BuyNft { offering_id: 1, nft_address: "hope1_address".to_string(),
+            token_id: None, max_price: Some(Uint128::new(9000)), proof: None, referrer: None };
+        let res = execute(deps.as_mut(), at(350), mock_info("buyer", &funds), msg).unwrap();
+        assert_eq!(res.messages.len(), 5);
+        assert_eq!(res.messages[1].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "seller".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(7600) }]
+        }));
+        assert_eq!(res.messages[4].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "buyer".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]
+        }));
+        let history = query_get_history(deps.as_ref(), "hope1_address".to_string(), vec!["1".to_string()]).unwrap();
+        assert_eq!(history[0].amount, Uint128::new(8000));
+
+        let msg = ExecuteMsg::BuyNft { offering_id: 2, nft_address: "hope1_address".to_string(),
+            token_id: None, max_price: None, proof: None, referrer: None };
+        let err = execute(deps.as_mut(), at(350), mock_info("buyer", &funds), msg).unwrap_err();
+        assert!(matches!(err, ContractError::NotEnoughFunds {}));
+    }
+
+    #[test]
+    fn offers() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"other_token".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap_err();
+        assert!(matches!(err, ContractError::DuplicateSymbol {}));
+        // A cw20 named like a native denom must not take over native escrow
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"juno_token".to_string(),
+            symbol:"ujuno".to_string()
+        }).unwrap();
+        let expires_at = mock_env().block.time.seconds() + 100;
+        let mut expired = mock_env();
+        expired.block.time = expired.block.time.plus_seconds(100);
+
+        let offer = |deps: DepsMut, buyer: &str, token_id: &str, amount: u128| {
+            execute(deps, mock_env(), mock_info(buyer, &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(amount) }]),
+                ExecuteMsg::MakeOffer { nft_address: "hope1_address".to_string(), token_id: token_id.to_string(), expires_at })
+        };
+        let err = offer(deps.as_mut(), "buyer1", "Hope.1", 0).unwrap_err();
+        assert!(matches!(err, ContractError::ZeroFunds {}));
+        offer(deps.as_mut(), "buyer1", "Hope.1", 1000).unwrap();
+        offer(deps.as_mut(), "buyer2", "Hope.1", 2000).unwrap();
+        offer(deps.as_mut(), "buyer3", "Hope.2", 3000).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"buyer4".to_string(),
+            amount:Uint128::new(4000),
+            msg:to_binary(&Cw20HookMsg::MakeOffer {
+                nft_address: "hope1_address".to_string(),
+                token_id: "Hope.1".to_string(),
+                expires_at
+            }).unwrap()
+        })).unwrap();
+
+        let offers = query_get_offers(deps.as_ref(), "hope1_address".to_string(), "Hope.1".to_string(), None, None).unwrap();
+        assert_eq!(offers.iter().map(|o| o.id).collect::<Vec<u64>>(), vec![1,2,4]);
+        assert_eq!(offers[2].offer.price, Asset { denom: "hope".to_string(), amount: Uint128::new(4000) });
+
+        // The holder of an unlisted token accepts by sending it in
+        let accept = |deps: DepsMut, env: Env, token_id: &str, offer_id: u64| {
+            execute(deps, env, mock_info("hope1_address", &[]), ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+                sender:"holder".to_string(),
+                token_id:token_id.to_string(),
+                msg:to_binary(&Cw721HookMsg::AcceptOffer { offer_id }).unwrap()
+            }))
+        };
+        let err = accept(deps.as_mut(), mock_env(), "Hope.2", 2).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+        let res = accept(deps.as_mut(), mock_env(), "Hope.1", 4).unwrap();
+        assert_eq!(res.messages.len(), 4);
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "hope1_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: "buyer4".to_string(),
+                token_id: "Hope.1".to_string(),
             }).unwrap(),
         }));
+        assert_eq!(res.messages[1].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "holder".to_string(),
+                amount: Uint128::new(3800),
+            }).unwrap()
+        }));
 
-      assert_eq!(res.messages[1].msg,CosmosMsg::Bank(BankMsg::Send {
-                to_address: "owner1".to_string(),
-                amount:vec![Coin{
-                    denom:"ujuno".to_string(),
-                    amount:Uint128::new(950000)
-                }]
+        // A listed token is accepted by the seller of the offering
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.2",
+            Asset { denom: "ujuno".to_string(), amount: Uint128::new(5000) });
+        let accept_listed = ExecuteMsg::AcceptOffer { nft_address: "hope1_address".to_string(), offering_id: 1, offer_id: 3 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("holder", &[]), accept_listed.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("seller", &[]), accept_listed).unwrap();
+        assert_eq!(res.messages[1].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "seller".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(2850) }]
         }));
+        assert!(query_get_ids(deps.as_ref(), mock_env(), "hope1_address".to_string()).unwrap().is_empty());
 
-        assert_eq!(res.messages[2].msg,CosmosMsg::Bank(BankMsg::Send {
-                to_address: "admin1".to_string(),
-                amount:vec![Coin{
-                    denom:"ujuno".to_string(),
-                    amount:Uint128::new(15000)
-                }]
+        // Cancelling refunds the buyer; after expiry anyone may trigger it
+        let cancel = |offer_id: u64| ExecuteMsg::CancelOffer {
+            nft_address: "hope1_address".to_string(),
+            token_id: "Hope.1".to_string(),
+            offer_id
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), cancel(1)).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer1", &[]), cancel(1)).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "buyer1".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]
+        }));
+
+        let err = accept(deps.as_mut(), expired.clone(), "Hope.1", 2).unwrap_err();
+        assert!(matches!(err, ContractError::OfferExpired {}));
+        let res = execute(deps.as_mut(), expired, mock_info("anyone", &[]), cancel(2)).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "buyer2".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(2000) }]
+        }));
+        assert!(query_get_offers(deps.as_ref(), "hope1_address".to_string(), "Hope.1".to_string(), None, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn collection_bids() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        add_collection(deps.as_mut(), "hope2_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+
+        let place = |deps: DepsMut, amount: u128, quantity: u64| {
+            execute(deps, mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(amount) }]),
+                ExecuteMsg::PlaceCollectionBid { nft_address: "hope1_address".to_string(), price: Uint128::new(1000), quantity, traits: None })
+        };
+        let err = place(deps.as_mut(), 2999, 3).unwrap_err();
+        assert!(matches!(err, ContractError::NotEnoughFunds {}));
+        let err = place(deps.as_mut(), 3001, 3).unwrap_err();
+        assert!(matches!(err, ContractError::TooMuchFunds {}));
+        let err = place(deps.as_mut(), 3000, 0).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidQuantity {}));
+        place(deps.as_mut(), 3000, 3).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"buyer".to_string(),
+            amount:Uint128::new(2000),
+            msg:to_binary(&Cw20HookMsg::PlaceCollectionBid {
+                nft_address: "hope1_address".to_string(),
+                price: Uint128::new(2000),
+                quantity: 1,
+                traits: None
+            }).unwrap()
+        })).unwrap();
+
+        let fill = |deps: DepsMut, nft_address: &str, holder: &str, token_id: &str, bid_id: u64| {
+            execute(deps, mock_env(), mock_info(nft_address, &[]), ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+                sender:holder.to_string(),
+                token_id:token_id.to_string(),
+                msg:to_binary(&Cw721HookMsg::FillCollectionBid { bid_id }).unwrap()
+            }))
+        };
+        // Tokens of another collection cannot fill the bid
+        let err = fill(deps.as_mut(), "hope2_address", "holder1", "Other.1", 1).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+
+        let res = fill(deps.as_mut(), "hope1_address", "holder1", "Hope.1", 1).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "hope1_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: "buyer".to_string(),
+                token_id: "Hope.1".to_string(),
+            }).unwrap(),
+        }));
+        assert_eq!(res.messages[1].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "holder1".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(950) }]
+        }));
+        fill(deps.as_mut(), "hope1_address", "holder2", "Hope.2", 1).unwrap();
+
+        let bids = query_get_collection_bids(deps.as_ref(), "hope1_address".to_string(), None, None).unwrap();
+        assert_eq!(bids[0].bid.quantity, 1);
+        assert_eq!(bids[1].bid.price, Asset { denom: "hope".to_string(), amount: Uint128::new(2000) });
+        assert_eq!(bids[1].bid.payment, AssetInfo::Token { contract_addr: "token_address".to_string() });
+
+        let cancel = ExecuteMsg::CancelCollectionBid { nft_address: "hope1_address".to_string(), bid_id: 1 };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("holder1", &[]), cancel.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), cancel).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "buyer".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]
+        }));
+
+        // The last unit closes the bid
+        let res = fill(deps.as_mut(), "hope1_address", "holder3", "Hope.3", 2).unwrap();
+        assert_eq!(res.messages[1].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "holder3".to_string(),
+                amount: Uint128::new(1900),
+            }).unwrap()
+        }));
+        assert!(query_get_collection_bids(deps.as_ref(), "hope1_address".to_string(), None, None).unwrap().is_empty());
+        assert_eq!(query_collection_info(deps.as_ref(), "hope1_address".to_string()).unwrap().sale_id, 3);
+    }
+
+    #[test]
+    fn trait_filtered_collection_bids() {
+        let mut deps = mock_dependencies();
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "hope1_address" => {
+                let token_id = match from_binary(msg).unwrap() {
+                    Cw721QueryMsg::NftInfo { token_id } => token_id,
+                    _ => panic!("unexpected query"),
+                };
+                let extension = match token_id.as_str() {
+                    "Hope.1" => Some(Metadata { attributes: Some(vec![
+                        Trait { trait_type: "background".to_string(), value: "gold".to_string() },
+                        Trait { trait_type: "eyes".to_string(), value: "laser".to_string() },
+                    ]) }),
+                    "Hope.2" => Some(Metadata { attributes: Some(vec![
+                        Trait { trait_type: "background".to_string(), value: "blue".to_string() },
+                    ]) }),
+                    _ => None,
+                };
+                SystemResult::Ok(ContractResult::Ok(to_binary(&NftInfoResponse { token_uri: None, extension }).unwrap()))
+            }
+            _ => SystemResult::Err(SystemError::NoSuchContract { addr: "unknown".to_string() }),
+        });
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+
+        execute(deps.as_mut(), mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(5000) }]),
+            ExecuteMsg::PlaceCollectionBid {
+                nft_address: "hope1_address".to_string(),
+                price: Uint128::new(5000),
+                quantity: 1,
+                traits: Some(vec![Trait { trait_type: "background".to_string(), value: "gold".to_string() }])
+            }).unwrap();
+
+        let fill = |deps: DepsMut, token_id: &str| {
+            execute(deps, mock_env(), mock_info("hope1_address", &[]), ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+                sender:"holder".to_string(),
+                token_id:token_id.to_string(),
+                msg:to_binary(&Cw721HookMsg::FillCollectionBid { bid_id: 1 }).unwrap()
+            }))
+        };
+        let err = fill(deps.as_mut(), "Hope.2").unwrap_err();
+        assert!(matches!(err, ContractError::TraitMismatch {}));
+        let err = fill(deps.as_mut(), "Hope.3").unwrap_err();
+        assert!(matches!(err, ContractError::TraitMismatch {}));
+
+        let res = fill(deps.as_mut(), "Hope.1").unwrap();
+        assert_eq!(res.messages[1].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "holder".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(4750) }]
+        }));
+        assert!(query_get_collection_bids(deps.as_ref(), "hope1_address".to_string(), None, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bundles() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        add_collection(deps.as_mut(), "hope2_address");
+
+        let item = |nft_address: &str, token_id: &str, percent: u64| BundleItem {
+            nft_address: nft_address.to_string(),
+            token_id: token_id.to_string(),
+            allocation: Decimal::percent(percent),
+            deposited: false
+        };
+        let create = |deps: DepsMut, items: Vec<BundleItem>| {
+            execute(deps, mock_env(), mock_info("seller", &[]), ExecuteMsg::CreateBundle {
+                price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(1001) },
+                items
+            })
+        };
+        let err = create(deps.as_mut(), vec![item("hope1_address", "Hope.1", 60), item("hope2_address", "Other.1", 30)]).unwrap_err();
+        assert!(matches!(err, ContractError::PortionError {}));
+        let err = create(deps.as_mut(), vec![item("hope1_address", "Hope.1", 60), item("hope3_address", "Other.1", 40)]).unwrap_err();
+        assert!(matches!(err, ContractError::WrongNFTContractError {}));
+        let err = create(deps.as_mut(), vec![item("hope1_address", "Hope.1", 50), item("hope1_address", "Hope.1", 50)]).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidBundle {}));
+        create(deps.as_mut(), vec![item("hope1_address", "Hope.1", 60), item("hope2_address", "Other.1", 40)]).unwrap();
+
+        let deposit = |deps: DepsMut, nft_address: &str, sender: &str, token_id: &str, bundle_id: u64| {
+            execute(deps, mock_env(), mock_info(nft_address, &[]), ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+                sender:sender.to_string(),
+                token_id:token_id.to_string(),
+                msg:to_binary(&Cw721HookMsg::DepositBundle { bundle_id }).unwrap()
+            }))
+        };
+        let buy = |deps: DepsMut, amount: u128| {
+            execute(deps, mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(amount) }]),
+                ExecuteMsg::BuyBundle { bundle_id: 1 })
+        };
+
+        let err = deposit(deps.as_mut(), "hope1_address", "someone", "Hope.1", 1).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let err = deposit(deps.as_mut(), "hope2_address", "seller", "Hope.1", 1).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidBundle {}));
+        deposit(deps.as_mut(), "hope1_address", "seller", "Hope.1", 1).unwrap();
+        let err = buy(deps.as_mut(), 1001).unwrap_err();
+        assert!(matches!(err, ContractError::BundleNotActive {}));
+        let res = deposit(deps.as_mut(), "hope2_address", "seller", "Other.1", 1).unwrap();
+        assert_eq!(res.attributes[2], attr("active", "true"));
+        assert!(query_get_bundles(deps.as_ref(), None, None).unwrap()[0].bundle.is_active());
+
+        // A cw20 registered under the native denom cannot pay for it
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"juno_token".to_string(),
+            symbol:"ujuno".to_string()
+        }).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("juno_token", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"buyer".to_string(),
+            amount:Uint128::new(1001),
+            msg:to_binary(&Cw20HookMsg::BuyBundle { bundle_id: 1 }).unwrap()
+        })).unwrap_err();
+        assert!(matches!(err, ContractError::UnexpectedDenom { .. }));
+
+        let err = buy(deps.as_mut(), 1000).unwrap_err();
+        assert!(matches!(err, ContractError::NotEnoughFunds {}));
+        let res = buy(deps.as_mut(), 1001).unwrap();
+        let payouts: Vec<(String, u128)> = res.messages.iter().filter_map(|m| match &m.msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => Some((to_address.clone(), amount[0].amount.u128())),
+            _ => None,
+        }).collect();
+        // 600 booked on hope1 and the 401 remainder on hope2, each under a 5% royalty
+        assert_eq!(payouts, vec![
+            ("seller".to_string(), 570), ("admin1".to_string(), 9), ("admin2".to_string(), 21),
+            ("seller".to_string(), 381), ("admin1".to_string(), 6), ("admin2".to_string(), 14),
+        ]);
+        assert_eq!(res.messages[4].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "hope2_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: "buyer".to_string(),
+                token_id: "Other.1".to_string(),
+            }).unwrap(),
         }));
-        assert_eq!(res.messages[3].msg,CosmosMsg::Bank(BankMsg::Send {
-                to_address: "admin2".to_string(),
-                amount:vec![Coin{
-                    denom:"ujuno".to_string(),
-                    amount:Uint128::new(35000)
-                }]
+        assert_eq!(query_collection_info(deps.as_ref(), "hope2_address".to_string()).unwrap().sale_id, 1);
+
+        // Cancelling returns only the escrowed tokens
+        create(deps.as_mut(), vec![item("hope1_address", "Hope.2", 50), item("hope1_address", "Hope.3", 50)]).unwrap();
+        deposit(deps.as_mut(), "hope1_address", "seller", "Hope.3", 2).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("seller", &[]), ExecuteMsg::CancelBundle { bundle_id: 2 }).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "hope1_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: "seller".to_string(),
+                token_id: "Hope.3".to_string(),
+            }).unwrap(),
         }));
-                                                                    
-        let ids =  query_get_ids(deps.as_ref(),"hope1_address".to_string()).unwrap();
-        let test_id:Vec<String> = vec![];
-        assert_eq!(ids,test_id);
-        
+        assert!(query_get_bundles(deps.as_ref(), None, None).unwrap().is_empty());
+    }
 
-         // Rearragnge the offering
+    #[test]
+    fn swaps() {
+        let mut deps = mock_dependencies();
+        // The taker owns Other.1, everything else sits in marketplace escrow
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { msg, .. } => match from_binary(msg) {
+                Ok(Cw721QueryMsg::OwnerOf { token_id, .. }) => {
+                    let owner = if token_id == "Other.1" { "taker" } else { MOCK_CONTRACT_ADDR };
+                    SystemResult::Ok(ContractResult::Ok(to_binary(&OwnerOfResponse { owner: owner.to_string(), approvals: vec![] }).unwrap()))
+                }
+                _ => SystemResult::Ok(ContractResult::Err("Unsupported query".to_string())),
+            },
+            _ => SystemResult::Err(SystemError::NoSuchContract { addr: "unknown".to_string() }),
+        });
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        add_collection(deps.as_mut(), "hope2_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+
+        let nft = |nft_address: &str, token_id: &str| NftRef { nft_address: nft_address.to_string(), token_id: token_id.to_string() };
+        let cw20 = |amount: u128| PaymentAsset { info: AssetInfo::Token { contract_addr: "token_address".to_string() }, amount: Uint128::new(amount) };
+        let native = |denom: &str, amount: u128| PaymentAsset { info: AssetInfo::NativeToken { denom: denom.to_string() }, amount: Uint128::new(amount) };
+        let create = ExecuteMsg::CreateSwap {
+            offered_nfts: vec![nft("hope1_address", "Hope.1")],
+            wanted_nfts: vec![nft("hope2_address", "Other.1")],
+            wanted_funds: vec![cw20(100), native("ustars", 50)],
+            counterparty: Some("taker".to_string())
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("maker", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(500) }]), create.clone()).unwrap();
 
-         //sell
-        let cw721_msg = SellNft{
-            list_price:Asset{
-                denom:"osmos".to_string(),
-                amount:Uint128::new(2000000)
-            }
+        let take = |deps: DepsMut, sender: &str, funds: &[Coin]| {
+            execute(deps, mock_env(), mock_info(sender, funds), ExecuteMsg::TakeSwap { swap_id: 1 })
+        };
+        let stars = [Coin{ denom:"ustars".to_string(), amount:Uint128::new(50) }];
+        let err = take(deps.as_mut(), "taker", &stars).unwrap_err();
+        assert!(matches!(err, ContractError::SwapNotActive {}));
+
+        let deposit = |deps: DepsMut, sender: &str| {
+            execute(deps, mock_env(), mock_info("hope1_address", &[]), ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
+                sender:sender.to_string(),
+                token_id:"Hope.1".to_string(),
+                msg:to_binary(&Cw721HookMsg::DepositSwap { swap_id: 1 }).unwrap()
+            }))
         };
+        let err = deposit(deps.as_mut(), "taker").unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        deposit(deps.as_mut(), "maker").unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"maker".to_string(),
+            amount:Uint128::new(300),
+            msg:to_binary(&Cw20HookMsg::FundSwap { swap_id: 1 }).unwrap()
+        })).unwrap();
+        let swap = &query_get_swaps(deps.as_ref(), None, None).unwrap()[0].swap;
+        assert!(swap.is_active());
+        assert_eq!(swap.offered_funds, vec![native("ujuno", 500), cw20(300)]);
+
+        let err = take(deps.as_mut(), "other", &stars).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let err = take(deps.as_mut(), "taker", &[]).unwrap_err();
+        assert!(matches!(err, ContractError::NotEnoughFunds {}));
+        let err = take(deps.as_mut(), "taker", &[Coin{ denom:"ustars".to_string(), amount:Uint128::new(60) }]).unwrap_err();
+        assert!(matches!(err, ContractError::TooMuchFunds {}));
+        let err = take(deps.as_mut(), "taker", &[stars[0].clone(), Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1) }]).unwrap_err();
+        assert!(matches!(err, ContractError::UnexpectedDenom { denom } if denom == "ujuno"));
+
+        let res = take(deps.as_mut(), "taker", &stars).unwrap();
+        let msgs: Vec<CosmosMsg> = res.messages.into_iter().map(|m| m.msg).collect();
+        assert_eq!(msgs, vec![
+            transfer_nft_msg(nft("hope1_address", "Hope.1"), &Addr::unchecked("taker")).unwrap(),
+            native("ujuno", 500).into_msg(Addr::unchecked("taker")).unwrap(),
+            cw20(300).into_msg(Addr::unchecked("taker")).unwrap(),
+            transfer_nft_msg(nft("hope2_address", "Other.1"), &Addr::unchecked("maker")).unwrap(),
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token_address".to_string(),
+                funds: vec![],
+                msg: to_binary(&Cw20ExecuteMsg::TransferFrom {
+                    owner: "taker".to_string(),
+                    recipient: "maker".to_string(),
+                    amount: Uint128::new(100),
+                }).unwrap()
+            }),
+            CosmosMsg::Bank(BankMsg::Send { to_address: "maker".to_string(), amount: stars.to_vec() }),
+        ]);
+        assert!(query_get_swaps(deps.as_ref(), None, None).unwrap().is_empty());
+
+        // Cancelling refunds only what was escrowed
+        execute(deps.as_mut(), mock_env(), mock_info("maker", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(500) }]), create).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("taker", &[]), ExecuteMsg::CancelSwap { swap_id: 2 }).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("maker", &[]), ExecuteMsg::CancelSwap { swap_id: 2 }).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].msg, native("ujuno", 500).into_msg(Addr::unchecked("maker")).unwrap());
+
+        // Repeating a wanted denom would let a taker pay once and collect twice
+        let err = execute(deps.as_mut(), mock_env(), mock_info("maker", &[]), ExecuteMsg::CreateSwap {
+            offered_nfts: vec![nft("hope1_address", "Hope.2")],
+            wanted_nfts: vec![],
+            wanted_funds: vec![native("ustars", 50), native("ustars", 50)],
+            counterparty: None
+        }).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidSwap {}));
+
+        // A swap has to offer something
+        let err = execute(deps.as_mut(), mock_env(), mock_info("maker", &[]), ExecuteMsg::CreateSwap {
+            offered_nfts: vec![],
+            wanted_nfts: vec![],
+            wanted_funds: vec![native("ustars", 50)],
+            counterparty: None
+        }).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidSwap {}));
+
+        // Escrowed NFTs cannot be pulled out by naming them as wanted
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.3", Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) });
+        execute(deps.as_mut(), mock_env(), mock_info("maker", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1) }]), ExecuteMsg::CreateSwap {
+            offered_nfts: vec![],
+            wanted_nfts: vec![nft("hope1_address", "Hope.3")],
+            wanted_funds: vec![],
+            counterparty: None
+        }).unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("maker", &[]), ExecuteMsg::TakeSwap { swap_id: 3 }).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+    }
 
-        let info = mock_info("hope1_address", &[]);
-        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
-            sender:"buyer1".to_string(),
-            token_id:"Hope.1".to_string(),
-            msg:to_binary(&cw721_msg).unwrap()
-        });
-         execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
-     
-         //sell
-         let cw721_msg = SellNft{
-            list_price:Asset{
-                denom:"ujuno".to_string(),
-                amount:Uint128::new(2000000)
-            }
+    #[test]
+    fn reserved_listings() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+
+        let reserved = |denom: &str| SellNft {
+            list_price: Asset { denom: denom.to_string(), amount: Uint128::new(1000) },
+            starts_at: None,
+            expires_at: None,
+            price_decay: None,
+            reserved_for: Some("friend".to_string()),
+            alt_prices: None,
+            peg_unit: None,
+            reserved_root: None,
+        };
+        list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", &reserved("ujuno")).unwrap();
+        list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.2", &reserved("hope")).unwrap();
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.3", Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) });
+
+        // Only the public listing shows up when browsing
+        assert_eq!(query_get_ids(deps.as_ref(), mock_env(), "hope1_address".to_string()).unwrap(), vec![3]);
+        // but can still be looked up by ID
+        let page = query_get_offering(deps.as_ref(), mock_env(), vec![1, 2, 3], "hope1_address".to_string()).unwrap();
+        assert_eq!(page.iter().map(|off| off.token_id.as_str()).collect::<Vec<_>>(), vec!["Hope.1", "Hope.2", "Hope.3"]);
+        assert_eq!(page[0].reserved_for, Some("friend".to_string()));
+
+        let buy = |deps: DepsMut, buyer: &str| {
+            execute(deps, mock_env(), mock_info(buyer, &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]), ExecuteMsg::BuyNft {
+                offering_id: 1,
+                nft_address: "hope1_address".to_string(),
+                token_id: None,
+                max_price: None,
+                proof: None,
+                referrer: None
+            })
         };
+        let err = buy(deps.as_mut(), "stranger").unwrap_err();
+        assert!(matches!(err, ContractError::ReservedOffering {}));
+        let res = buy(deps.as_mut(), "friend").unwrap();
+        assert_eq!(res.messages[1].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "seller".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(950) }]
+        }));
 
-          let info = mock_info("hope1_address", &[]);
-          let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
-            sender:"buyer2".to_string(),
-            token_id:"Hope.2".to_string(),
-            msg:to_binary(&cw721_msg).unwrap()
+        let buy_with_token = |deps: DepsMut, buyer: &str| {
+            execute(deps, mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+                sender:buyer.to_string(),
+                amount:Uint128::new(1000),
+                msg:to_binary(&BuyNft{
+                    offering_id:2,
+                    nft_address:"hope1_address".to_string(),
+                    token_id:None,
+                    max_price:None,
+                    proof:None,
+                    referrer:None
+                }).unwrap()
+            }))
+        };
+        let err = buy_with_token(deps.as_mut(), "stranger").unwrap_err();
+        assert!(matches!(err, ContractError::ReservedOffering {}));
+        buy_with_token(deps.as_mut(), "friend").unwrap();
+    }
+
+    #[test]
+    fn non_custodial_listings() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+
+        // "Hope.1" is held by `owner` with the marketplace approved if `approved`
+        let set_owner = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, owner: &str, approved: bool| {
+            let owner = owner.to_string();
+            deps.querier.update_wasm(move |query| match query {
+                WasmQuery::Smart { msg, .. } => match from_binary(msg).unwrap() {
+                    Cw721QueryMsg::OwnerOf { token_id, .. } if token_id == "Hope.1" => {
+                        let approvals = if approved {
+                            vec![cw721::Approval { spender: mock_env().contract.address.to_string(), expires: cw721::Expiration::Never {} }]
+                        } else {
+                            vec![]
+                        };
+                        SystemResult::Ok(ContractResult::Ok(to_binary(&OwnerOfResponse { owner: owner.clone(), approvals }).unwrap()))
+                    }
+                    _ => SystemResult::Ok(ContractResult::Err("token not found".to_string())),
+                },
+                _ => panic!("unexpected query"),
             });
-         execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        };
+        let list = |deps: DepsMut, sender: &str| {
+            execute(deps, mock_env(), mock_info(sender, &[]), ExecuteMsg::ListNft {
+                nft_address: "hope1_address".to_string(),
+                token_id: "Hope.1".to_string(),
+                price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) }
+            })
+        };
 
-         //buy
+        set_owner(&mut deps, "seller", false);
+        let err = list(deps.as_mut(), "seller").unwrap_err();
+        assert!(matches!(err, ContractError::StaleListing {}));
+        set_owner(&mut deps, "seller", true);
+        let err = list(deps.as_mut(), "someone").unwrap_err();
+        assert!(matches!(err, ContractError::StaleListing {}));
+        list(deps.as_mut(), "seller").unwrap();
+        assert!(query_get_offering(deps.as_ref(), mock_env(), vec![1], "hope1_address".to_string()).unwrap()[0].non_custodial);
+
+        let buy = |deps: DepsMut| {
+            execute(deps, mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]), ExecuteMsg::BuyNft {
+                offering_id: 1,
+                nft_address: "hope1_address".to_string(),
+                token_id: None,
+                max_price: None,
+                proof: None,
+                referrer: None
+            })
+        };
 
-        let info = mock_info("test_buyer2", &[Coin{
-            denom:"osmos".to_string(),
-            amount:Uint128::new(2000000)
-        }]);
-        let msg = ExecuteMsg::BuyNft { offering_id: "1".to_string(), nft_address: "hope1_address".to_string() };
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        // The token moved, so the listing is stale until pruned
+        set_owner(&mut deps, "someone", true);
+        let err = buy(deps.as_mut()).unwrap_err();
+        assert!(matches!(err, ContractError::StaleListing {}));
+        assert_eq!(query_get_stale_offerings(deps.as_ref(), mock_env(), "hope1_address".to_string(), None, None).unwrap(), vec![1]);
+
+        set_owner(&mut deps, "seller", true);
+        assert!(query_get_stale_offerings(deps.as_ref(), mock_env(), "hope1_address".to_string(), None, None).unwrap().is_empty());
+        let res = buy(deps.as_mut()).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "hope1_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: "buyer".to_string(),
+                token_id: "Hope.1".to_string(),
+            }).unwrap(),
+        }));
 
-        let id = query_get_ids(deps.as_ref(), "hope1_address".to_string()).unwrap();
-        let collection_info = query_collection_info(deps.as_ref(),"hope1_address".to_string()).unwrap();
-        assert_eq!(collection_info.offering_id,1);  
-        assert_eq!(id,vec!["1"]);
+        // Withdrawing just drops the listing
+        list(deps.as_mut(), "seller").unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("seller", &[]),
+            ExecuteMsg::WithdrawNft { offering_id: 2, nft_address: "hope1_address".to_string() }).unwrap();
+        assert!(res.messages.is_empty());
+
+        list(deps.as_mut(), "seller").unwrap();
+        set_owner(&mut deps, "seller", false);
+        let prune = ExecuteMsg::PruneStale { nft_address: "hope1_address".to_string(), offering_ids: vec![3] };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), prune).unwrap();
+        assert_eq!(res.attributes[1], attr("pruned", "1"));
+        assert!(query_get_ids(deps.as_ref(), mock_env(), "hope1_address".to_string()).unwrap().is_empty());
+    }
 
-         let cw721_msg = SellNft{
-            list_price:Asset{
-                denom:"hope".to_string(),
-                amount:Uint128::new(2000000)
-            }
+    #[test]
+    fn multi_currency_listings() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+
+        let price = |denom: &str, amount: u128| Asset { denom: denom.to_string(), amount: Uint128::new(amount) };
+        let listing = |alt_prices: Vec<Asset>| SellNft {
+            list_price: price("ujuno", 1000),
+            starts_at: None,
+            expires_at: None,
+            price_decay: None,
+            reserved_for: None,
+            alt_prices: Some(alt_prices),
+            peg_unit: None,
+            reserved_root: None,
         };
+        let err = list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.0", &listing(vec![price("ujuno", 900)])).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPrice {}));
+        let err = list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.0", &listing(vec![price("hope", 0)])).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidPrice {}));
+        list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", &listing(vec![price("hope", 500), price("uatom", 20)])).unwrap();
+        list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.2", &listing(vec![price("hope", 500), price("uatom", 20)])).unwrap();
+
+        let buy = |deps: DepsMut, funds: &[Coin]| {
+            execute(deps, mock_env(), mock_info("buyer", funds), ExecuteMsg::BuyNft {
+                offering_id: 1,
+                nft_address: "hope1_address".to_string(),
+                token_id: None,
+                max_price: None,
+                proof: None,
+                referrer: None
+            })
+        };
+        let err = buy(deps.as_mut(), &[Coin{ denom:"uosmo".to_string(), amount:Uint128::new(20) }]).unwrap_err();
+        assert!(matches!(err, ContractError::UnexpectedDenom { denom } if denom == "uosmo"));
+        let err = buy(deps.as_mut(), &[Coin{ denom:"uatom".to_string(), amount:Uint128::new(19) }]).unwrap_err();
+        assert!(matches!(err, ContractError::NotEnoughFunds {}));
+        let res = buy(deps.as_mut(), &[Coin{ denom:"uatom".to_string(), amount:Uint128::new(20) }]).unwrap();
+        assert_eq!(res.messages[1].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "seller".to_string(),
+            amount: vec![Coin{ denom:"uatom".to_string(), amount:Uint128::new(19) }]
+        }));
 
-        let info = mock_info("hope1_address", &[]);
-        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
-            sender:"buyer3".to_string(),
-            token_id:"Hope.3".to_string(),
-            msg:to_binary(&cw721_msg).unwrap()
-        });
+        let res = execute(deps.as_mut(), mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"buyer".to_string(),
+            amount:Uint128::new(500),
+            msg:to_binary(&BuyNft{
+                offering_id:2,
+                nft_address:"hope1_address".to_string(),
+                token_id:None,
+                max_price:None,
+                proof:None,
+                referrer:None
+            }).unwrap()
+        })).unwrap();
+        assert_eq!(res.messages[1].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "seller".to_string(),
+                amount: Uint128::new(475),
+            }).unwrap()
+        }));
 
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        // History and TVL are booked in the currency that was paid
+        let history = query_get_history(deps.as_ref(), "hope1_address".to_string(), vec!["1".to_string(), "2".to_string()]).unwrap();
+        assert_eq!((history[0].denom.as_str(), history[0].amount), ("uatom", Uint128::new(20)));
+        assert_eq!((history[1].denom.as_str(), history[1].amount), ("hope", Uint128::new(500)));
+        assert_eq!(query_get_tvl(deps.as_ref(), "hope1_address".to_string(), "uatom".to_string()).unwrap(), Uint128::new(20));
+        assert_eq!(query_get_tvl(deps.as_ref(), "hope1_address".to_string(), "hope".to_string()).unwrap(), Uint128::new(500));
+        assert_eq!(query_get_tvl(deps.as_ref(), "hope1_address".to_string(), "ujuno".to_string()).unwrap(), Uint128::zero());
+    }
 
-        let ids = query_get_ids(deps.as_ref(),"hope1_address".to_string()).unwrap();
-        assert_eq!(ids,vec!["1".to_string(),"2".to_string()]);
-        let offerings = query_get_offering(deps.as_ref(),vec!["1".to_string(),"2".to_string()],"hope1_address".to_string()).unwrap();
-        assert_eq!(offerings,vec![QueryOfferingsResult{
-            id:"1".to_string(),
-            token_id:"Hope.2".to_string(),
-            list_price:Asset { denom: "ujuno".to_string(),  amount:Uint128::new(2000000) },
-            seller:"buyer2".to_string()
-        },QueryOfferingsResult{
-            id:"2".to_string(),
-            token_id:"Hope.3".to_string(),
-            list_price:Asset { denom: "hope".to_string(),  amount:Uint128::new(2000000) },
-            seller:"buyer3".to_string()
-        }]);
+    #[test]
+    fn buy_many() {
+        let setup = |denom: &str| {
+            let mut deps = mock_dependencies();
+            setup_contract(deps.as_mut());
+            add_collection(deps.as_mut(), "hope1_address");
+            add_collection(deps.as_mut(), "hope2_address");
+            execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+                address:"token_address".to_string(),
+                symbol:"hope".to_string()
+            }).unwrap();
+            let price = |amount: u128| Asset { denom: denom.to_string(), amount: Uint128::new(amount) };
+            sell_nft(deps.as_mut(), "hope1_address", "seller1", "Hope.1", price(1000));
+            sell_nft(deps.as_mut(), "hope1_address", "seller1", "Hope.2", price(2000));
+            sell_nft(deps.as_mut(), "hope2_address", "seller2", "Other.1", price(500));
+            deps
+        };
+        let items = vec![
+            ("hope1_address".to_string(), 1),
+            ("hope1_address".to_string(), 2),
+            ("hope2_address".to_string(), 1),
+        ];
+        let buy_many = |deps: DepsMut, max_total: u128, funds: &[Coin]| {
+            execute(deps, mock_env(), mock_info("buyer", funds),
+                ExecuteMsg::BuyMany { items: items.clone(), max_total: Uint128::new(max_total) })
+        };
+        let juno = |amount: u128| Coin { denom: "ujuno".to_string(), amount: Uint128::new(amount) };
+
+        let mut deps = setup("ujuno");
+        let err = buy_many(deps.as_mut(), 3500, &[]).unwrap_err();
+        assert!(matches!(err, ContractError::NoFunds {}));
+        let err = buy_many(deps.as_mut(), 3500, &[juno(4000), Coin { denom: "uatom".to_string(), amount: Uint128::new(1) }]).unwrap_err();
+        assert!(matches!(err, ContractError::MultipleDenoms {}));
+        let err = buy_many(setup("ujuno").as_mut(), 3000, &[juno(4000)]).unwrap_err();
+        assert!(matches!(err, ContractError::OfferingMismatch {}));
+        let err = buy_many(setup("ujuno").as_mut(), 3500, &[juno(3000)]).unwrap_err();
+        assert!(matches!(err, ContractError::NotEnoughFunds {}));
+
+        let res = buy_many(deps.as_mut(), 3500, &[juno(4000)]).unwrap();
+        let msgs: Vec<CosmosMsg> = res.messages.into_iter().map(|m| m.msg).collect();
+        let send = |to: &str, amount: u128| CosmosMsg::Bank(BankMsg::Send { to_address: to.to_string(), amount: vec![juno(amount)] });
+        assert_eq!(msgs[..3], [
+            transfer_nft_msg(NftRef { nft_address: "hope1_address".to_string(), token_id: "Hope.1".to_string() }, &Addr::unchecked("buyer")).unwrap(),
+            transfer_nft_msg(NftRef { nft_address: "hope1_address".to_string(), token_id: "Hope.2".to_string() }, &Addr::unchecked("buyer")).unwrap(),
+            transfer_nft_msg(NftRef { nft_address: "hope2_address".to_string(), token_id: "Other.1".to_string() }, &Addr::unchecked("buyer")).unwrap(),
+        ]);
+        // One transfer per recipient, then the refund
+        assert_eq!(msgs[3..], [send("seller1", 2850), send("admin1", 52), send("admin2", 123), send("seller2", 475), send("buyer", 500)]);
+        assert_eq!(query_collection_info(deps.as_ref(), "hope1_address".to_string()).unwrap().sale_id, 2);
+        assert!(query_get_ids(deps.as_ref(), mock_env(), "hope1_address".to_string()).unwrap().is_empty());
+
+        // A sold item fails the whole sweep
+        let err = buy_many(deps.as_mut(), 3500, &[juno(4000)]).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::NotFound { .. })));
+
+        let mut deps = setup("hope");
+        let res = execute(deps.as_mut(), mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"buyer".to_string(),
+            amount:Uint128::new(3600),
+            msg:to_binary(&Cw20HookMsg::BuyMany { items: items.clone(), max_total: Uint128::new(3500) }).unwrap()
+        })).unwrap();
+        assert_eq!(res.messages.len(), 8);
+        assert_eq!(res.messages[7].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "buyer".to_string(),
+                amount: Uint128::new(100),
+            }).unwrap()
+        }));
+    }
 
-        let cw20_msg= BuyNft{
-            offering_id:"2".to_string(),
-            nft_address:"hope1_address".to_string()
+    #[test]
+    fn native_purchase_funds() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) });
+
+        let buy = |deps: DepsMut, funds: &[(u128, &str)]| {
+            let funds: Vec<Coin> = funds.iter().map(|(amount, denom)| Coin { denom: denom.to_string(), amount: Uint128::new(*amount) }).collect();
+            execute(deps, mock_env(), mock_info("buyer", &funds), ExecuteMsg::BuyNft {
+                offering_id: 1,
+                nft_address: "hope1_address".to_string(),
+                token_id: None,
+                max_price: None,
+                proof: None,
+                referrer: None
+            })
         };
+        assert!(matches!(buy(deps.as_mut(), &[]).unwrap_err(), ContractError::NoFunds {}));
+        assert!(matches!(buy(deps.as_mut(), &[(0, "ujuno")]).unwrap_err(), ContractError::ZeroFunds {}));
+        assert!(matches!(buy(deps.as_mut(), &[(1000, "ujuno"), (1, "uatom")]).unwrap_err(), ContractError::MultipleDenoms {}));
+        assert!(matches!(buy(deps.as_mut(), &[(1000, "uatom")]).unwrap_err(), ContractError::UnexpectedDenom { .. }));
+        assert!(matches!(buy(deps.as_mut(), &[(999, "ujuno")]).unwrap_err(), ContractError::NotEnoughFunds {}));
+        assert!(matches!(buy(deps.as_mut(), &[(1001, "ujuno")]).unwrap_err(), ContractError::TooMuchFunds {}));
+        let res = buy(deps.as_mut(), &[(1000, "ujuno")]).unwrap();
+        assert_eq!(res.messages.len(), 4);
+    }
 
-        let info = mock_info("token_address", &[]);
-        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg{
-            sender:"test_buyer3".to_string(),
-            amount:Uint128::new(2000000),
-            msg:to_binary(&cw20_msg).unwrap()
+    #[test]
+    fn lazy_minting() {
+        use cosmwasm_std::coins;
+        use cw_multi_test::{App, ContractWrapper, Executor};
+
+        let mut app = App::new(|router, _, storage| {
+            router.bank.init_balance(storage, &Addr::unchecked("buyer"), coins(10000, "ujuno")).unwrap();
         });
-        let res = execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();    
-        assert_eq!(res.messages[1].msg,CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: "token_address".to_string(),
-                funds: vec![],
-                msg: to_binary(&Cw20ExecuteMsg::Transfer { 
-                    recipient: "buyer3".to_string(), 
-                    amount:Uint128::new(1900000),
-                 }).unwrap()
-         }));
+        let market_code = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let nft_code = app.store_code(Box::new(ContractWrapper::new(
+            cw721_base::entry::execute, cw721_base::entry::instantiate, cw721_base::entry::query)));
+
+        let owner = Addr::unchecked("owner");
+        let market = app.instantiate_contract(market_code, owner.clone(), &InstantiateMsg { owner: "owner".to_string() }, &[], "market", None).unwrap();
+        let nft = app.instantiate_contract(nft_code, owner.clone(), &cw721_base::InstantiateMsg {
+            name: "Hope".to_string(),
+            symbol: "HOPE".to_string(),
+            minter: market.to_string()
+        }, &[], "nft", None).unwrap();
+        app.execute_contract(owner.clone(), market.clone(), &ExecuteMsg::AddCollection {
+            royalty_portion: Decimal::from_ratio(5u128, 100u128),
+            members: vec![
+                UserInfo { address: "admin1".to_string(), portion: Decimal::percent(30) },
+                UserInfo { address: "admin2".to_string(), portion: Decimal::percent(70) }
+            ],
+            nft_address: nft.to_string(),
+            offering_id: 0,
+            sale_id: 0
+        }, &[]).unwrap();
+
+        let add = |app: &mut App, sender: &Addr, token_id: &str| app.execute_contract(sender.clone(), market.clone(), &ExecuteMsg::AddMintTemplate {
+            nft_address: nft.to_string(),
+            token_id: token_id.to_string(),
+            token_uri: Some(format!("ipfs://{}", token_id)),
+            price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) }
+        }, &[]);
+        let err = add(&mut app, &Addr::unchecked("creator"), "Hope.1").unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::Unauthorized {}));
+        add(&mut app, &owner, "Hope.1").unwrap();
+        add(&mut app, &owner, "Hope.2").unwrap();
+        app.execute_contract(owner.clone(), market.clone(), &ExecuteMsg::RemoveMintTemplate {
+            nft_address: nft.to_string(),
+            template_id: 2
+        }, &[]).unwrap();
+
+        let templates: Vec<QueryMintTemplatesResult> = app.wrap().query_wasm_smart(&market, &QueryMsg::GetMintTemplates {
+            address: nft.to_string(),
+            start_after: None,
+            limit: None
+        }).unwrap();
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates[0].id, 1);
+
+        let buy = |app: &mut App, template_id: u64, amount: u128| app.execute_contract(Addr::unchecked("buyer"), market.clone(), &ExecuteMsg::BuyMint {
+            nft_address: nft.to_string(),
+            template_id
+        }, &coins(amount, "ujuno"));
+        let err = buy(&mut app, 1, 999).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::NotEnoughFunds {}));
+        assert!(buy(&mut app, 2, 1000).is_err());
+        buy(&mut app, 1, 1000).unwrap();
+        // Sold out
+        assert!(buy(&mut app, 1, 1000).is_err());
+
+        let balance = |app: &App, addr: &str| app.wrap().query_balance(addr, "ujuno").unwrap().amount.u128();
+        assert_eq!(balance(&app, "buyer"), 9000);
+        assert_eq!(balance(&app, "admin1"), 300);
+        assert_eq!(balance(&app, "admin2"), 700);
+        let info: cw721::AllNftInfoResponse<cw721_base::Extension> = app.wrap().query_wasm_smart(&nft, &Cw721QueryMsg::AllNftInfo {
+            token_id: "Hope.1".to_string(),
+            include_expired: None
+        }).unwrap();
+        assert_eq!(info.access.owner, "buyer");
+        assert_eq!(info.info.token_uri, Some("ipfs://Hope.1".to_string()));
+
+        let history: Vec<SaleInfo> = app.wrap().query_wasm_smart(&market, &QueryMsg::GetSaleHistory {
+            address: nft.to_string(),
+            id: vec!["1".to_string()]
+        }).unwrap();
+        assert_eq!(history[0].from, market.to_string());
+    }
 
-         assert_eq!(res.messages[2].msg,CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: "token_address".to_string(),
-                funds: vec![],
-                msg: to_binary(&Cw20ExecuteMsg::Transfer { 
-                    recipient: "admin1".to_string(), 
-                    amount:Uint128::new(30000),
-                 }).unwrap()
-         }));
+    #[test]
+    fn launchpad() {
+        use cosmwasm_std::coins;
+        use cw_multi_test::{App, ContractWrapper, Executor};
 
-          assert_eq!(res.messages[3].msg,CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: "token_address".to_string(),
-                funds: vec![],
-                msg: to_binary(&Cw20ExecuteMsg::Transfer { 
-                    recipient: "admin2".to_string(), 
-                    amount:Uint128::new(70000),
-                 }).unwrap()
-         }));
+        let mut app = App::new(|router, _, storage| {
+            router.bank.init_balance(storage, &Addr::unchecked("early"), coins(10000, "ujuno")).unwrap();
+            router.bank.init_balance(storage, &Addr::unchecked("buyer"), coins(10000, "ujuno")).unwrap();
+        });
+        let market_code = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let nft_code = app.store_code(Box::new(ContractWrapper::new(
+            cw721_base::entry::execute, cw721_base::entry::instantiate, cw721_base::entry::query)));
+
+        let owner = Addr::unchecked("owner");
+        let market = app.instantiate_contract(market_code, owner.clone(), &InstantiateMsg { owner: "owner".to_string() }, &[], "market", None).unwrap();
+        let nft = app.instantiate_contract(nft_code, owner.clone(), &cw721_base::InstantiateMsg {
+            name: "Hope".to_string(),
+            symbol: "HOPE".to_string(),
+            minter: market.to_string()
+        }, &[], "nft", None).unwrap();
+        app.execute_contract(owner.clone(), market.clone(), &ExecuteMsg::AddCollection {
+            royalty_portion: Decimal::from_ratio(5u128, 100u128),
+            members: vec![
+                UserInfo { address: "admin1".to_string(), portion: Decimal::percent(30) },
+                UserInfo { address: "admin2".to_string(), portion: Decimal::percent(70) }
+            ],
+            nft_address: nft.to_string(),
+            offering_id: 0,
+            sale_id: 0
+        }, &[]).unwrap();
+
+        let now = app.block_info().time.seconds();
+        let phase = |name: &str, price: u128, start_time: u64, end_time: u64, per_wallet_limit: Option<u32>, allowlist: Option<Vec<String>>| LaunchPhase {
+            name: name.to_string(),
+            price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(price) },
+            start_time,
+            end_time,
+            per_wallet_limit,
+            allowlist,
+            merkle_root: None
+        };
+        let set_launch = |app: &mut App, phases: Vec<LaunchPhase>| app.execute_contract(owner.clone(), market.clone(), &ExecuteMsg::SetLaunch {
+            nft_address: nft.to_string(),
+            phases,
+            supply_cap: 4,
+            base_token_uri: Some("ipfs://hope".to_string())
+        }, &[]);
+        // Overlapping phases
+        let err = set_launch(&mut app, vec![
+            phase("allowlist", 500, now + 10, now + 100, Some(2), Some(vec!["early".to_string()])),
+            phase("public", 1000, now + 50, now + 200, Some(3), None)
+        ]).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::InvalidLaunch {}));
+        set_launch(&mut app, vec![
+            phase("allowlist", 500, now + 10, now + 100, Some(2), Some(vec!["early".to_string()])),
+            phase("public", 1000, now + 100, now + 200, Some(3), None)
+        ]).unwrap();
+
+        let mint = |app: &mut App, sender: &str, quantity: u32, amount: u128| app.execute_contract(Addr::unchecked(sender), market.clone(), &ExecuteMsg::LaunchMint {
+            nft_address: nft.to_string(),
+            quantity,
+            proof: None
+        }, &coins(amount, "ujuno"));
+        let err = mint(&mut app, "early", 1, 500).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::LaunchNotActive {}));
+
+        app.update_block(|block| block.time = block.time.plus_seconds(10));
+        let err = mint(&mut app, "buyer", 1, 500).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::NotAllowlisted {}));
+        let err = mint(&mut app, "early", 3, 1500).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::MintLimitReached {}));
+        let err = mint(&mut app, "early", 2, 500).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::NotEnoughFunds {}));
+        mint(&mut app, "early", 2, 1000).unwrap();
+        let err = mint(&mut app, "early", 1, 500).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::MintLimitReached {}));
+
+        app.update_block(|block| block.time = block.time.plus_seconds(90));
+        let err = mint(&mut app, "buyer", 3, 3000).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::SoldOut {}));
+        mint(&mut app, "buyer", 2, 2000).unwrap();
+        let err = mint(&mut app, "early", 1, 1000).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::SoldOut {}));
+
+        app.update_block(|block| block.time = block.time.plus_seconds(100));
+        let err = mint(&mut app, "buyer", 1, 1000).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::LaunchNotActive {}));
+
+        let balance = |app: &App, addr: &str| app.wrap().query_balance(addr, "ujuno").unwrap().amount.u128();
+        assert_eq!(balance(&app, "early"), 9000);
+        assert_eq!(balance(&app, "buyer"), 8000);
+        assert_eq!(balance(&app, "admin1"), 900);
+        assert_eq!(balance(&app, "admin2"), 2100);
+
+        let launch: Option<Launch> = app.wrap().query_wasm_smart(&market, &QueryMsg::GetLaunch { address: nft.to_string() }).unwrap();
+        assert_eq!(launch.unwrap().minted, 4);
+        let minted: u32 = app.wrap().query_wasm_smart(&market, &QueryMsg::GetLaunchMinted {
+            address: nft.to_string(),
+            phase: 1,
+            wallet: "buyer".to_string()
+        }).unwrap();
+        assert_eq!(minted, 2);
+        let owner_of: OwnerOfResponse = app.wrap().query_wasm_smart(&nft, &Cw721QueryMsg::OwnerOf {
+            token_id: "launch.3".to_string(),
+            include_expired: None
+        }).unwrap();
+        assert_eq!(owner_of.owner, "buyer");
+        let info: cw721::NftInfoResponse<cw721_base::Extension> = app.wrap().query_wasm_smart(&nft, &Cw721QueryMsg::NftInfo {
+            token_id: "launch.1".to_string()
+        }).unwrap();
+        assert_eq!(info.token_uri, Some("ipfs://hope/1".to_string()));
+
+        // Templates cannot take launch token ids
+        let err = app.execute_contract(owner.clone(), market.clone(), &ExecuteMsg::AddMintTemplate {
+            nft_address: nft.to_string(),
+            token_id: "launch.5".to_string(),
+            token_uri: None,
+            price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) }
+        }, &[]).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::ReservedTokenId {}));
+
+        // New phases start with fresh wallet counts
+        let now = app.block_info().time.seconds();
+        set_launch(&mut app, vec![phase("public", 1000, now, now + 100, Some(3), None)]).unwrap();
+        let minted: u32 = app.wrap().query_wasm_smart(&market, &QueryMsg::GetLaunchMinted {
+            address: nft.to_string(),
+            phase: 0,
+            wallet: "early".to_string()
+        }).unwrap();
+        assert_eq!(minted, 0);
+    }
 
+    #[test]
+    fn merkle_allowlists() {
+        use crate::merkle::generate_fixture;
 
-        let offerings = query_get_offering(deps.as_ref(),vec!["1".to_string(),"2".to_string()],"hope1_address".to_string()).unwrap();
-        assert_eq!(offerings,vec![QueryOfferingsResult{
-            id:"1".to_string(),
-            token_id:"Hope.2".to_string(),
-            list_price:Asset { denom: "ujuno".to_string(),  amount:Uint128::new(2000000) },
-            seller:"buyer2".to_string()
-        }]);
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+
+        let wallets: Vec<String> = (0..200).map(|i| format!("wallet{}", i)).collect();
+        let wallets: Vec<&str> = wallets.iter().map(String::as_str).collect();
+        let (root, proofs) = generate_fixture(&wallets);
+
+        let reserved = |reserved_root: HexBinary| SellNft {
+            list_price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) },
+            starts_at: None,
+            expires_at: None,
+            price_decay: None,
+            reserved_for: None,
+            alt_prices: None,
+            peg_unit: None,
+            reserved_root: Some(reserved_root),
+        };
+        let err = list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", &reserved(HexBinary::from(vec![1u8; 20]))).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMerkleRoot {}));
+        list_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", &reserved(root.clone())).unwrap();
+        assert!(query_get_ids(deps.as_ref(), mock_env(), "hope1_address".to_string()).unwrap().is_empty());
+
+        let buy = |deps: DepsMut, buyer: &str, proof: Option<Vec<HexBinary>>| {
+            execute(deps, mock_env(), mock_info(buyer, &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]), ExecuteMsg::BuyNft {
+                offering_id: 1,
+                nft_address: "hope1_address".to_string(),
+                token_id: None,
+                max_price: None,
+                proof,
+                referrer: None
+            })
+        };
+        assert!(matches!(buy(deps.as_mut(), "wallet42", None).unwrap_err(), ContractError::ReservedOffering {}));
+        assert!(matches!(buy(deps.as_mut(), "stranger", Some(proofs[42].clone())).unwrap_err(), ContractError::ReservedOffering {}));
+        assert!(matches!(buy(deps.as_mut(), "wallet42", Some(proofs[43].clone())).unwrap_err(), ContractError::ReservedOffering {}));
+        buy(deps.as_mut(), "wallet42", Some(proofs[42].clone())).unwrap();
+
+        let env = mock_env();
+        let now = env.block.time.seconds();
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::SetLaunch {
+            nft_address: "hope1_address".to_string(),
+            phases: vec![LaunchPhase {
+                name: "allowlist".to_string(),
+                price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(500) },
+                start_time: now,
+                end_time: now + 100,
+                per_wallet_limit: None,
+                allowlist: Some(vec!["friend".to_string()]),
+                merkle_root: Some(root)
+            }],
+            supply_cap: 10,
+            base_token_uri: None
+        }).unwrap();
+        let mint = |deps: DepsMut, minter: &str, proof: Option<Vec<HexBinary>>| {
+            execute(deps, mock_env(), mock_info(minter, &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(500) }]), ExecuteMsg::LaunchMint {
+                nft_address: "hope1_address".to_string(),
+                quantity: 1,
+                proof
+            })
+        };
+        assert!(matches!(mint(deps.as_mut(), "wallet7", None).unwrap_err(), ContractError::NotAllowlisted {}));
+        assert!(matches!(mint(deps.as_mut(), "stranger", Some(proofs[7].clone())).unwrap_err(), ContractError::NotAllowlisted {}));
+        mint(deps.as_mut(), "wallet7", Some(proofs[7].clone())).unwrap();
+        mint(deps.as_mut(), "friend", None).unwrap();
+        let res = mint(deps.as_mut(), "wallet199", Some(proofs[199].clone())).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "hope1_address".to_string(),
+            funds: vec![],
+            msg: to_binary(&cw721_base::ExecuteMsg::Mint(cw721_base::MintMsg::<cw721_base::Extension> {
+                token_id: "launch.3".to_string(),
+                owner: "wallet199".to_string(),
+                token_uri: None,
+                extension: None
+            })).unwrap()
+        }));
+    }
 
-        let cw721_msg = SellNft{
-            list_price:Asset{
-                denom:"raw".to_string(),
-                amount:Uint128::new(2000000)
-            }
+    #[test]
+    fn protocol_fees() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+
+        let set_fee = |deps: DepsMut, sender: &str, nft_address: Option<&str>, percent: u64| {
+            execute(deps, mock_env(), mock_info(sender, &[]), ExecuteMsg::SetProtocolFee {
+                nft_address: nft_address.map(str::to_string),
+                fee: Some(Decimal::percent(percent))
+            })
         };
+        assert!(matches!(set_fee(deps.as_mut(), "seller", None, 2).unwrap_err(), ContractError::Unauthorized {}));
+        assert!(matches!(set_fee(deps.as_mut(), "owner", None, 11).unwrap_err(), ContractError::InvalidFee {}));
+        set_fee(deps.as_mut(), "owner", None, 2).unwrap();
+
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) });
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]), ExecuteMsg::BuyNft {
+            offering_id: 1,
+            nft_address: "hope1_address".to_string(),
+            token_id: None,
+            max_price: None,
+            proof: None,
+            referrer: None
+        }).unwrap();
+        let sent: Vec<(String, Uint128)> = res.messages.iter().filter_map(|msg| match &msg.msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => Some((to_address.clone(), amount[0].amount)),
+            _ => None,
+        }).collect();
+        assert_eq!(sent, vec![
+            ("seller".to_string(), Uint128::new(930)),
+            ("admin1".to_string(), Uint128::new(15)),
+            ("admin2".to_string(), Uint128::new(35))
+        ]);
+
+        // The collection override applies to cw20 sales as well
+        set_fee(deps.as_mut(), "owner", Some("hope1_address"), 1).unwrap();
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.2", Asset { denom: "hope".to_string(), amount: Uint128::new(1000) });
+        let res = execute(deps.as_mut(), mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"buyer".to_string(),
+            amount:Uint128::new(1000),
+            msg:to_binary(&BuyNft{
+                offering_id:2,
+                nft_address:"hope1_address".to_string(),
+                token_id:None,
+                max_price:None,
+                proof:None,
+                referrer:None
+            }).unwrap()
+        })).unwrap();
+        assert_eq!(res.messages[1].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_address".to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "seller".to_string(),
+                amount: Uint128::new(940)
+            }).unwrap(),
+            funds: vec![]
+        }));
 
-        let info = mock_info("hope1_address", &[]);
-        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg{
-            sender:"owner1".to_string(),
-            token_id:"Hope.1".to_string(),
-            msg:to_binary(&cw721_msg).unwrap()
-        });
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        // Primary sales pay the fee before the members split
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddMintTemplate {
+            nft_address: "hope1_address".to_string(),
+            token_id: "Hope.3".to_string(),
+            token_uri: None,
+            price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) }
+        }).unwrap();
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]), ExecuteMsg::BuyMint {
+            nft_address: "hope1_address".to_string(),
+            template_id: 1
+        }).unwrap();
+        let sent: Vec<(String, Uint128)> = res.messages.iter().filter_map(|msg| match &msg.msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => Some((to_address.clone(), amount[0].amount)),
+            _ => None,
+        }).collect();
+        assert_eq!(sent, vec![
+            ("admin1".to_string(), Uint128::new(297)),
+            ("admin2".to_string(), Uint128::new(693))
+        ]);
+
+        assert_eq!(query_accrued_fees(deps.as_ref()).unwrap(), vec![
+            Asset { denom: "hope".to_string(), amount: Uint128::new(10) },
+            Asset { denom: "ujuno".to_string(), amount: Uint128::new(30) }
+        ]);
+
+        let withdraw = |deps: DepsMut, sender: &str| {
+            execute(deps, mock_env(), mock_info(sender, &[]), ExecuteMsg::WithdrawFees { denoms: None })
+        };
+        assert!(matches!(withdraw(deps.as_mut(), "owner").unwrap_err(), ContractError::TreasuryNotSet {}));
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::SetTreasury {
+            address: "treasury".to_string()
+        }).unwrap();
+        assert!(matches!(withdraw(deps.as_mut(), "seller").unwrap_err(), ContractError::Unauthorized {}));
+        let res = withdraw(deps.as_mut(), "owner").unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(res.messages[1].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "treasury".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(30) }]
+        }));
+        assert!(query_accrued_fees(deps.as_ref()).unwrap().is_empty());
+        assert!(withdraw(deps.as_mut(), "owner").unwrap().messages.is_empty());
+    }
 
-        let cw20_msg= BuyNft{
-            offering_id:"2".to_string(),
-            nft_address:"hope1_address".to_string()
+    #[test]
+    fn pull_payments() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+
+        let set_pull = ExecuteMsg::SetPullPayments { nft_address: "hope1_address".to_string(), enabled: true };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("seller", &[]), set_pull.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), set_pull).unwrap();
+
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) });
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.2", Asset { denom: "hope".to_string(), amount: Uint128::new(2000) });
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]), ExecuteMsg::BuyNft {
+            offering_id: 1,
+            nft_address: "hope1_address".to_string(),
+            token_id: None,
+            max_price: None,
+            proof: None,
+            referrer: None
+        }).unwrap();
+        // Only the NFT moves, proceeds wait in the ledger
+        assert_eq!(res.messages.len(), 1);
+        let res = execute(deps.as_mut(), mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"buyer".to_string(),
+            amount:Uint128::new(2000),
+            msg:to_binary(&BuyNft{
+                offering_id:2,
+                nft_address:"hope1_address".to_string(),
+                token_id:None,
+                max_price:None,
+                proof:None,
+                referrer:None
+            }).unwrap()
+        })).unwrap();
+        assert_eq!(res.messages.len(), 1);
+
+        assert_eq!(query_pending_payments(deps.as_ref(), "seller".to_string()).unwrap(), vec![
+            Asset { denom: "hope".to_string(), amount: Uint128::new(1900) },
+            Asset { denom: "ujuno".to_string(), amount: Uint128::new(950) }
+        ]);
+        assert_eq!(query_pending_payments(deps.as_ref(), "admin2".to_string()).unwrap(), vec![
+            Asset { denom: "hope".to_string(), amount: Uint128::new(70) },
+            Asset { denom: "ujuno".to_string(), amount: Uint128::new(35) }
+        ]);
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info("seller", &[]), ExecuteMsg::Claim {
+            assets: Some(vec!["ujuno".to_string()])
+        }).unwrap();
+        assert_eq!(res.messages[0].msg, CosmosMsg::Bank(BankMsg::Send {
+            to_address: "seller".to_string(),
+            amount: vec![Coin{ denom:"ujuno".to_string(), amount:Uint128::new(950) }]
+        }));
+        assert_eq!(query_pending_payments(deps.as_ref(), "seller".to_string()).unwrap().len(), 1);
+
+        // A keeper pays out on behalf of a member
+        let res = execute(deps.as_mut(), mock_env(), mock_info("keeper", &[]), ExecuteMsg::ClaimFor {
+            address: "admin1".to_string(),
+            assets: None
+        }).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(res.messages[0].msg, CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token_address".to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "admin1".to_string(),
+                amount: Uint128::new(30)
+            }).unwrap(),
+            funds: vec![]
+        }));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("admin1", &[]), ExecuteMsg::Claim { assets: None }).unwrap_err();
+        assert!(matches!(err, ContractError::NothingToClaim {}));
+    }
+
+    #[test]
+    fn referrals() {
+        let mut deps = mock_dependencies();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::AddTokenAddress {
+            address:"token_address".to_string(),
+            symbol:"hope".to_string()
+        }).unwrap();
+
+        let set_bps = |deps: DepsMut, nft_address: Option<&str>, bps: u64| {
+            execute(deps, mock_env(), mock_info("owner", &[]), ExecuteMsg::SetReferralFee {
+                nft_address: nft_address.map(str::to_string),
+                bps: Some(bps)
+            })
         };
+        assert!(matches!(set_bps(deps.as_mut(), None, 501).unwrap_err(), ContractError::InvalidFee {}));
+        set_bps(deps.as_mut(), None, 100).unwrap();
+
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) });
+        let buy = |deps: DepsMut, referrer: &str| {
+            execute(deps, mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]), ExecuteMsg::BuyNft {
+                offering_id: 1,
+                nft_address: "hope1_address".to_string(),
+                token_id: None,
+                max_price: None,
+                proof: None,
+                referrer: Some(referrer.to_string())
+            })
+        };
+        assert!(matches!(buy(deps.as_mut(), "buyer").unwrap_err(), ContractError::InvalidReferrer {}));
+        assert!(matches!(buy(deps.as_mut(), "seller").unwrap_err(), ContractError::InvalidReferrer {}));
+        let res = buy(deps.as_mut(), "partner").unwrap();
+        let sent: Vec<(String, Uint128)> = res.messages.iter().filter_map(|msg| match &msg.msg {
+            CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => Some((to_address.clone(), amount[0].amount)),
+            _ => None,
+        }).collect();
+        assert_eq!(sent, vec![
+            ("seller".to_string(), Uint128::new(940)),
+            ("admin1".to_string(), Uint128::new(15)),
+            ("admin2".to_string(), Uint128::new(35)),
+            ("partner".to_string(), Uint128::new(10))
+        ]);
+
+        // Collection override, credited to the ledger on a pull-payment collection
+        set_bps(deps.as_mut(), Some("hope1_address"), 200).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::SetPullPayments {
+            nft_address: "hope1_address".to_string(),
+            enabled: true
+        }).unwrap();
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.2", Asset { denom: "hope".to_string(), amount: Uint128::new(2000) });
+        execute(deps.as_mut(), mock_env(), mock_info("token_address", &[]), ExecuteMsg::Receive(Cw20ReceiveMsg{
+            sender:"buyer".to_string(),
+            amount:Uint128::new(2000),
+            msg:to_binary(&BuyNft{
+                offering_id:2,
+                nft_address:"hope1_address".to_string(),
+                token_id:None,
+                max_price:None,
+                proof:None,
+                referrer:Some("partner".to_string())
+            }).unwrap()
+        })).unwrap();
+        assert_eq!(query_pending_payments(deps.as_ref(), "partner".to_string()).unwrap(), vec![
+            Asset { denom: "hope".to_string(), amount: Uint128::new(40) }
+        ]);
+        assert_eq!(query_pending_payments(deps.as_ref(), "seller".to_string()).unwrap(), vec![
+            Asset { denom: "hope".to_string(), amount: Uint128::new(1860) }
+        ]);
+
+        assert_eq!(query_referral_volume(deps.as_ref(), "partner".to_string()).unwrap(), vec![
+            Asset { denom: "hope".to_string(), amount: Uint128::new(2000) },
+            Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) }
+        ]);
+    }
 
-        let info = mock_info("raw_address", &[]);
-        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg{
-            sender:"test_buyer4".to_string(),
-            amount:Uint128::new(2000000),
-            msg:to_binary(&cw20_msg).unwrap()
+    #[test]
+    fn cw2981_royalties() {
+        let mut deps = mock_dependencies();
+        // 10% to the artist for Hope.1, Hope.2 has no cw2981 answer and
+        // Hope.3 names an address that does not validate
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "hope1_address" => {
+                match from_binary(msg) {
+                    Ok(Cw2981QueryMsg::Extension { msg: Cw2981Query::RoyaltyInfo { token_id, sale_price } }) if token_id != "Hope.2" => {
+                        SystemResult::Ok(ContractResult::Ok(to_binary(&RoyaltiesInfoResponse {
+                            address: if token_id == "Hope.1" { "artist" } else { "X" }.to_string(),
+                            royalty_amount: sale_price.multiply_ratio(10u128, 100u128)
+                        }).unwrap()))
+                    }
+                    _ => SystemResult::Ok(ContractResult::Err("Unsupported query".to_string())),
+                }
+            }
+            _ => SystemResult::Err(SystemError::NoSuchContract { addr: "unknown".to_string() }),
         });
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        setup_contract(deps.as_mut());
+        add_collection(deps.as_mut(), "hope1_address");
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info("seller", &[]), ExecuteMsg::SetRoyaltySource {
+            nft_address: "hope1_address".to_string(),
+            cw2981: true
+        }).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), ExecuteMsg::SetRoyaltySource {
+            nft_address: "hope1_address".to_string(),
+            cw2981: true
+        }).unwrap();
+
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) });
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.2", Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) });
+        let buy = |deps: DepsMut, offering_id: u64| {
+            let res = execute(deps, mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]), ExecuteMsg::BuyNft {
+                offering_id,
+                nft_address: "hope1_address".to_string(),
+                token_id: None,
+                max_price: None,
+                proof: None,
+                referrer: None
+            }).unwrap();
+            res.messages.iter().filter_map(|msg| match &msg.msg {
+                CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => Some((to_address.clone(), amount[0].amount.u128())),
+                _ => None,
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(buy(deps.as_mut(), 1), vec![("seller".to_string(), 900), ("artist".to_string(), 100)]);
+        // Falls back to the MEMBERS split
+        assert_eq!(buy(deps.as_mut(), 2), vec![("seller".to_string(), 950), ("admin1".to_string(), 15), ("admin2".to_string(), 35)]);
+
+        sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.3", Asset { denom: "ujuno".to_string(), amount: Uint128::new(1000) });
+        let err = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(1000) }]), ExecuteMsg::BuyNft {
+            offering_id: 3,
+            nft_address: "hope1_address".to_string(),
+            token_id: None,
+            max_price: None,
+            proof: None,
+            referrer: None
+        }).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::GenericErr { .. })));
+
+        let history = query_get_history(deps.as_ref(), "hope1_address".to_string(), vec!["1".to_string(), "2".to_string()]).unwrap();
+        assert_eq!(history[0].royalty_source, Some(RoyaltySource::Cw2981));
+        assert_eq!(history[1].royalty_source, Some(RoyaltySource::Members));
+    }
 
-        assert_eq!(offerings,vec![QueryOfferingsResult{
-            id:"1".to_string(),
-            token_id:"Hope.2".to_string(),
-            list_price:Asset { denom: "ujuno".to_string(),  amount:Uint128::new(2000000) },
-            seller:"buyer2".to_string()
-        }]);
+    /// Members with portions proportional to `weights`, summing to exactly one.
+    fn weighted_members(weights: &[u64]) -> Vec<UserInfo> {
+        let total: u64 = weights.iter().sum();
+        let mut allocated = Decimal::zero();
+        weights.iter().enumerate().map(|(i, weight)| {
+            let portion = if i + 1 == weights.len() {
+                Decimal::one() - allocated
+            } else {
+                Decimal::from_ratio(*weight, total)
+            };
+            allocated += portion;
+            UserInfo { address: format!("member{}", i), portion }
+        }).collect()
+    }
 
-        let juno_tvl = query_get_tvl(deps.as_ref(),"hope1_address".to_string(),"ujuno".to_string()).unwrap();
-        let hope_tvl = query_get_tvl(deps.as_ref(),"hope1_address".to_string(),"hope".to_string()).unwrap();
-        let osmos_tvl = query_get_tvl(deps.as_ref(),"hope1_address".to_string(),"osmos".to_string()).unwrap();
-        let raw_tvl  = query_get_tvl(deps.as_ref(),"hope1_address".to_string(),"raw".to_string()).unwrap();
-        println!("{}","juno".to_string());
-        assert_eq!(juno_tvl,Uint128::new(1000000));
-        
-        println!("{}","hope".to_string());
-        assert_eq!(hope_tvl,Uint128::new(2000000));
+    proptest::proptest! {
+        #[test]
+        fn portion_splits_conserve_total(
+            total in 0u128..(u64::MAX as u128),
+            weights in proptest::collection::vec(1u64..1000, 1..8)
+        ) {
+            let shares = split_by_portion(Uint128::new(total), weighted_members(&weights));
+            proptest::prop_assert_eq!(shares.len(), weights.len());
+            proptest::prop_assert_eq!(shares.iter().map(|(_, amount)| amount.u128()).sum::<u128>(), total);
+        }
 
-        println!("{}","osmos".to_string());
-        assert_eq!(osmos_tvl,Uint128::new(2000000));
+        #[test]
+        fn sale_payouts_conserve_price(
+            price in 1u128..1_000_000_000_000,
+            royalty_permille in 0u64..=1000,
+            weights in proptest::collection::vec(1u64..1000, 1..6),
+            fee_percent in 0u64..=10,
+            referral_bps in 0u64..=500,
+            referred in proptest::bool::ANY,
+            cw2981_permille in proptest::option::of(0u128..=1000)
+        ) {
+            let mut deps = mock_dependencies();
+            deps.querier.update_wasm(move |query| match (query, cw2981_permille) {
+                (WasmQuery::Smart { msg, .. }, Some(permille)) => match from_binary(msg) {
+                    Ok(Cw2981QueryMsg::Extension { msg: Cw2981Query::RoyaltyInfo { sale_price, .. } }) => {
+                        SystemResult::Ok(ContractResult::Ok(to_binary(&RoyaltiesInfoResponse {
+                            address: "artist".to_string(),
+                            royalty_amount: sale_price.multiply_ratio(permille, 1000u128)
+                        }).unwrap()))
+                    }
+                    _ => SystemResult::Ok(ContractResult::Err("Unsupported query".to_string())),
+                },
+                _ => SystemResult::Ok(ContractResult::Err("Unsupported query".to_string())),
+            });
+            setup_contract(deps.as_mut());
+            let owner = mock_info("owner", &[]);
+            execute(deps.as_mut(), mock_env(), owner.clone(), ExecuteMsg::AddCollection {
+                royalty_portion: Decimal::permille(royalty_permille),
+                members: weighted_members(&weights),
+                nft_address: "hope1_address".to_string(),
+                offering_id: 0,
+                sale_id: 0
+            }).unwrap();
+            // Cuts that would add up to more than the price are refused
+            let fee_set = execute(deps.as_mut(), mock_env(), owner.clone(), ExecuteMsg::SetProtocolFee {
+                nft_address: None,
+                fee: Some(Decimal::percent(fee_percent))
+            }).is_ok();
+            let referral_set = execute(deps.as_mut(), mock_env(), owner.clone(), ExecuteMsg::SetReferralFee {
+                nft_address: None,
+                bps: Some(referral_bps)
+            }).is_ok();
+            let total = Decimal::permille(royalty_permille) + Decimal::percent(fee_percent) + Decimal::from_ratio(referral_bps, 10_000u128);
+            proptest::prop_assert_eq!(fee_set && referral_set, total <= Decimal::one());
+            execute(deps.as_mut(), mock_env(), owner, ExecuteMsg::SetRoyaltySource {
+                nft_address: "hope1_address".to_string(),
+                cw2981: cw2981_permille.is_some()
+            }).unwrap();
+
+            sell_nft(deps.as_mut(), "hope1_address", "seller", "Hope.1", Asset { denom: "ujuno".to_string(), amount: Uint128::new(price) });
+            let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[Coin{ denom:"ujuno".to_string(), amount:Uint128::new(price) }]), ExecuteMsg::BuyNft {
+                offering_id: 1,
+                nft_address: "hope1_address".to_string(),
+                token_id: None,
+                max_price: None,
+                proof: None,
+                referrer: referred.then(|| "partner".to_string())
+            }).unwrap();
+
+            let paid: u128 = res.messages.iter().map(|msg| match &msg.msg {
+                CosmosMsg::Bank(BankMsg::Send { amount, .. }) => amount[0].amount.u128(),
+                _ => 0,
+            }).sum();
+            let fees: u128 = query_accrued_fees(deps.as_ref()).unwrap().iter().map(|fee| fee.amount.u128()).sum();
+            proptest::prop_assert_eq!(paid + fees, price);
+        }
+    }
 
-         println!("{}","raw".to_string());
-        assert_eq!(raw_tvl,Uint128::new(2000000));
+    mod mock_oracle {
+        use cosmwasm_std::{to_binary, Binary, Decimal, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdResult};
+        use cw_storage_plus::Map;
+        use schemars::JsonSchema;
+        use serde::{Deserialize, Serialize};
+        use crate::package::{OracleQueryMsg, OraclePriceResponse};
 
-        let collection_info = query_collection_info(deps.as_ref(), "hope1_address".to_string()).unwrap();
-        assert_eq!(collection_info.sale_id,4);
-        let _sale_history = query_get_history(deps.as_ref(), "hope1_address".to_string(), vec!["1".to_string(),"2".to_string(),"3".to_string(),"4".to_string()]).unwrap();
-        
-        let tvl_all = query_all_tvl(deps.as_ref(), "hope1_address".to_string(), vec!["ujuno".to_string(),"hope".to_string(),"osmos".to_string(),"xyz".to_string()]).unwrap();
-        assert_eq!(tvl_all,vec![TvlInfo{
-            denom:"ujuno".to_string(),
-            amount:Uint128::new(1000000)
-        },TvlInfo{
-            denom:"hope".to_string(),
-            amount:Uint128::new(2000000)
-        },TvlInfo{
-            denom:"osmos".to_string(),
-            amount:Uint128::new(2000000)
-        },TvlInfo{
-            denom:"xyz".to_string(),
-            amount:Uint128::new(0)
-        }]);
+        const RATES: Map<(&str,&str), Decimal> = Map::new("rates");
 
-         let info = mock_info("owner", &[]);
-        let msg = ExecuteMsg::SetTvl { address: "hope1_address".to_string(), tvl: vec![TvlInfo{
-            denom:"ujuno".to_string(),
-            amount:Uint128::new(0)
-        }] };
-        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+        pub struct SetRate {
+            pub base: String,
+            pub quote: String,
+            pub rate: Decimal,
+        }
 
-        let tvl_all = query_all_tvl(deps.as_ref(), "hope1_address".to_string(), vec!["ujuno".to_string()]).unwrap();
-        assert_eq!(tvl_all,vec![TvlInfo{
-            denom:"ujuno".to_string(),
-            amount:Uint128::new(0)
-        }]);
-       
+        pub fn instantiate(_deps: DepsMut, _env: Env, _info: MessageInfo, _msg: Empty) -> StdResult<Response> {
+            Ok(Response::new())
+        }
+
+        pub fn execute(deps: DepsMut, _env: Env, _info: MessageInfo, msg: SetRate) -> StdResult<Response> {
+            RATES.save(deps.storage, (&msg.base, &msg.quote), &msg.rate)?;
+            Ok(Response::new())
+        }
+
+        pub fn query(deps: Deps, _env: Env, msg: OracleQueryMsg) -> StdResult<Binary> {
+            match msg {
+                OracleQueryMsg::Price { base, quote } => to_binary(&OraclePriceResponse {
+                    rate: RATES.load(deps.storage, (&base, &quote))?
+                }),
+            }
+        }
+    }
+
+    #[test]
+    fn oracle_pegged_listings() {
+        use cosmwasm_std::{coins, Empty};
+        use cw_multi_test::{App, ContractWrapper, Executor};
+
+        let mut app = App::new(|router, _, storage| {
+            router.bank.init_balance(storage, &Addr::unchecked("buyer"), coins(10000, "ujuno")).unwrap();
+        });
+        let market_code = app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let oracle_code = app.store_code(Box::new(ContractWrapper::new(mock_oracle::execute, mock_oracle::instantiate, mock_oracle::query)));
+        let nft_code = app.store_code(Box::new(ContractWrapper::new(
+            cw721_base::entry::execute, cw721_base::entry::instantiate, cw721_base::entry::query)));
+
+        let owner = Addr::unchecked("owner");
+        let market = app.instantiate_contract(market_code, owner.clone(), &InstantiateMsg { owner: "owner".to_string() }, &[], "market", None).unwrap();
+        let oracle = app.instantiate_contract(oracle_code, owner.clone(), &Empty {}, &[], "oracle", None).unwrap();
+        let nft = app.instantiate_contract(nft_code, owner.clone(), &cw721_base::InstantiateMsg {
+            name: "Hope".to_string(),
+            symbol: "HOPE".to_string(),
+            minter: "owner".to_string()
+        }, &[], "nft", None).unwrap();
+
+        app.execute_contract(owner.clone(), market.clone(), &ExecuteMsg::AddCollection {
+            royalty_portion: Decimal::from_ratio(5u128, 100u128),
+            members: vec![UserInfo { address: "admin1".to_string(), portion: Decimal::one() }],
+            nft_address: nft.to_string(),
+            offering_id: 0,
+            sale_id: 0
+        }, &[]).unwrap();
+        app.execute_contract(owner.clone(), nft.clone(), &cw721_base::ExecuteMsg::Mint(cw721_base::MintMsg::<cw721_base::Extension> {
+            token_id: "Hope.1".to_string(),
+            owner: "seller".to_string(),
+            token_uri: None,
+            extension: None
+        }), &[]).unwrap();
+        let set_rate = |app: &mut App, rate: u64| {
+            app.execute_contract(owner.clone(), oracle.clone(), &mock_oracle::SetRate {
+                base: "uusd".to_string(),
+                quote: "ujuno".to_string(),
+                rate: Decimal::percent(rate)
+            }, &[]).unwrap();
+        };
+        set_rate(&mut app, 250);
+
+        // 400uusd payable in ujuno
+        let list = |app: &mut App| app.execute_contract(Addr::unchecked("seller"), nft.clone(), &cw721_base::ExecuteMsg::<cw721_base::Extension>::SendNft {
+            contract: market.to_string(),
+            token_id: "Hope.1".to_string(),
+            msg: to_binary(&SellNft {
+                list_price: Asset { denom: "ujuno".to_string(), amount: Uint128::new(400) },
+                starts_at: None,
+                expires_at: None,
+                price_decay: None,
+                reserved_for: None,
+                alt_prices: None,
+                peg_unit: Some("uusd".to_string()),
+                reserved_root: None,
+            }).unwrap()
+        }, &[]);
+        let err = list(&mut app).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::OracleNotSet {}));
+        app.execute_contract(owner.clone(), market.clone(), &ExecuteMsg::SetOracle { address: Some(oracle.to_string()) }, &[]).unwrap();
+        list(&mut app).unwrap();
+
+        let price: Asset = app.wrap().query_wasm_smart(&market, &QueryMsg::GetCurrentPrice {
+            address: nft.to_string(),
+            offering_id: 1
+        }).unwrap();
+        assert_eq!(price.amount, Uint128::new(1000));
+
+        let buy = |app: &mut App, max_price: Option<u128>, amount: u128| app.execute_contract(Addr::unchecked("buyer"), market.clone(), &ExecuteMsg::BuyNft {
+            offering_id: 1,
+            nft_address: nft.to_string(),
+            token_id: None,
+            max_price: max_price.map(Uint128::new),
+            proof: None,
+            referrer: None
+        }, &coins(amount, "ujuno"));
+        let err = buy(&mut app, None, 1000).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::SlippageBoundRequired {}));
+        let err = buy(&mut app, Some(900), 900).unwrap_err();
+        assert!(matches!(err.downcast().unwrap(), ContractError::OfferingMismatch {}));
+
+        // The rate moves in the buyer's favour before the purchase lands
+        set_rate(&mut app, 200);
+        buy(&mut app, Some(900), 900).unwrap();
+
+        let balance = |app: &App, addr: &str| app.wrap().query_balance(addr, "ujuno").unwrap().amount.u128();
+        assert_eq!(balance(&app, "buyer"), 9200);
+        assert_eq!(balance(&app, "seller"), 760);
+        assert_eq!(balance(&app, "admin1"), 40);
+        let owner_of: OwnerOfResponse = app.wrap().query_wasm_smart(&nft, &Cw721QueryMsg::OwnerOf {
+            token_id: "Hope.1".to_string(),
+            include_expired: None
+        }).unwrap();
+        assert_eq!(owner_of.owner, "buyer");
     }
 }
-    
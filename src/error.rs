@@ -1,7 +1,8 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
@@ -33,10 +34,121 @@ pub enum ContractError {
     #[error("Too Much Funds")]
     TooMuchFunds {},
 
+    #[error("No Funds")]
+    NoFunds {},
+
+    #[error("Zero Funds")]
+    ZeroFunds {},
+
+    #[error("Only one denom can be sent")]
+    MultipleDenoms {},
+
+    #[error("Unexpected denom {denom}")]
+    UnexpectedDenom { denom: String },
+
     #[error("Wrong Collection")]
     WrongCollection {},
 
 
     #[error("Escrow not expired")]
     NotExpired {},
+
+    #[error("Invalid Price")]
+    InvalidPrice {},
+
+    #[error("Invalid Listing Time")]
+    InvalidListingTime {},
+
+    #[error("Listing Not Started")]
+    ListingNotStarted {},
+
+    #[error("Listing Expired")]
+    ListingExpired {},
+
+    #[error("Auction Ended")]
+    AuctionEnded {},
+
+    #[error("Auction Not Ended")]
+    AuctionNotEnded {},
+
+    #[error("Auction Has Bids")]
+    AuctionHasBids {},
+
+    #[error("Bid too low, minimum is {min_bid}")]
+    BidTooLow { min_bid: Uint128 },
+
+    #[error("Offer Expired")]
+    OfferExpired {},
+
+    #[error("Invalid Quantity")]
+    InvalidQuantity {},
+
+    #[error("Oracle is not set")]
+    OracleNotSet {},
+
+    #[error("Pegged offerings need a max price")]
+    SlippageBoundRequired {},
+
+    #[error("Token is not owned by the seller or not approved to the marketplace")]
+    StaleListing {},
+
+    #[error("Offering is reserved for another buyer")]
+    ReservedOffering {},
+
+    #[error("Invalid Bundle")]
+    InvalidBundle {},
+
+    #[error("Bundle is not fully escrowed")]
+    BundleNotActive {},
+
+    #[error("Invalid Swap")]
+    InvalidSwap {},
+
+    #[error("Swap is not fully escrowed")]
+    SwapNotActive {},
+
+    #[error("Token does not have the required traits")]
+    TraitMismatch {},
+
+    #[error("Merkle root must be a 32 byte sha256 hash")]
+    InvalidMerkleRoot {},
+
+    #[error("Protocol fee too high")]
+    InvalidFee {},
+
+    #[error("Treasury is not set")]
+    TreasuryNotSet {},
+
+    #[error("Buyer or seller cannot be the referrer")]
+    InvalidReferrer {},
+
+    #[error("Nothing to claim")]
+    NothingToClaim {},
+
+    #[error("Invalid Launch")]
+    InvalidLaunch {},
+
+    #[error("No launch phase is active")]
+    LaunchNotActive {},
+
+    #[error("Wallet is not on the phase allowlist")]
+    NotAllowlisted {},
+
+    #[error("Mint limit reached for this phase")]
+    MintLimitReached {},
+
+    #[error("Sold Out")]
+    SoldOut {},
+
+    #[error("Token id is reserved for launch mints")]
+    ReservedTokenId {},
+
+    #[error("Token symbol is already registered")]
+    DuplicateSymbol {},
+
+    #[error("Offering does not match the expected token or price")]
+    OfferingMismatch {},
+
+    #[error("Cannot migrate from {previous_contract}")]
+    CannotMigrate { previous_contract: String },
 }
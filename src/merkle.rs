@@ -0,0 +1,79 @@
+use cosmwasm_std::HexBinary;
+use sha2::{Digest, Sha256};
+
+/// Leaf of an allowlist tree, the sha256 of the bech32 address.
+pub fn leaf_hash(address: &str) -> [u8; 32] {
+  Sha256::digest(address.as_bytes()).into()
+}
+
+/// Pairs are hashed in sorted order, so proofs carry no left/right flags.
+fn hash_pair(a: &[u8], b: &[u8]) -> [u8; 32] {
+  let (first, second) = if a <= b { (a, b) } else { (b, a) };
+  Sha256::new().chain_update(first).chain_update(second).finalize().into()
+}
+
+/// Whether `proof` links `address` to the sha256 Merkle `root`.
+pub fn verify_proof(root: &HexBinary, address: &str, proof: &[HexBinary]) -> bool {
+  if proof.iter().any(|node| node.len() != 32) {
+    return false;
+  }
+  let computed = proof
+    .iter()
+    .fold(leaf_hash(address), |hash, node| hash_pair(&hash, node.as_slice()));
+  computed.as_slice() == root.as_slice()
+}
+
+pub fn is_valid_root(root: &HexBinary) -> bool {
+  root.len() == 32
+}
+
+/// Builds an allowlist tree, returning the root and a proof per address.
+/// An odd node out is carried up to the next level unchanged.
+#[cfg(test)]
+pub fn generate_fixture(addresses: &[&str]) -> (HexBinary, Vec<Vec<HexBinary>>) {
+  let mut level: Vec<[u8; 32]> = addresses.iter().map(|address| leaf_hash(address)).collect();
+  let mut positions: Vec<usize> = (0..addresses.len()).collect();
+  let mut proofs: Vec<Vec<HexBinary>> = vec![vec![]; addresses.len()];
+  while level.len() > 1 {
+    for (proof, position) in proofs.iter_mut().zip(positions.iter_mut()) {
+      let sibling = *position ^ 1;
+      if sibling < level.len() {
+        proof.push(HexBinary::from(level[sibling].to_vec()));
+      }
+      *position /= 2;
+    }
+    level = level
+      .chunks(2)
+      .map(|pair| match pair {
+        [a, b] => hash_pair(a, b),
+        [a] => *a,
+        _ => unreachable!(),
+      })
+      .collect();
+  }
+  (HexBinary::from(level[0].to_vec()), proofs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn proofs() {
+    let addresses: Vec<String> = (0..1000).map(|i| format!("juno1wallet{}", i)).collect();
+    let addresses: Vec<&str> = addresses.iter().map(String::as_str).collect();
+    let (root, proofs) = generate_fixture(&addresses);
+    assert!(is_valid_root(&root));
+    for (address, proof) in addresses.iter().zip(proofs.iter()) {
+      assert!(verify_proof(&root, address, proof));
+    }
+    assert!(!verify_proof(&root, "juno1outsider", &proofs[0]));
+    assert!(!verify_proof(&root, addresses[1], &proofs[0]));
+    assert!(!verify_proof(&root, addresses[0], &proofs[0][1..]));
+    assert!(!verify_proof(&root, addresses[0], &[]));
+
+    let (single, proofs) = generate_fixture(&["juno1solo"]);
+    assert!(proofs[0].is_empty());
+    assert!(verify_proof(&single, "juno1solo", &[]));
+  }
+}
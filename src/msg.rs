@@ -2,9 +2,10 @@
 use cw20::Cw20ReceiveMsg;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use crate::{state::{Asset,UserInfo, TvlInfo, SaleInfo}, package::QueryOfferingsResult};
-use cosmwasm_std::{Decimal};
+use crate::{state::{Asset,UserInfo, TvlInfo, SaleInfo, PriceDecay, Trait, BundleItem, NftRef, LaunchPhase}, package::QueryOfferingsResult};
+use cosmwasm_std::{Decimal, HexBinary, Uint128};
 use cw721::Cw721ReceiveMsg;
+use crate::asset::Asset as PaymentAsset;
 
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -17,9 +18,64 @@ pub struct InstantiateMsg {
 pub enum ExecuteMsg {
  ReceiveNft(Cw721ReceiveMsg),
  Receive(Cw20ReceiveMsg),
- BuyNft{offering_id:String,nft_address:String},
- WithdrawNft{offering_id:String,nft_address:String},
+ /// `proof` places the buyer in the Merkle root of a reserved offering.
+ /// `referrer` earns the referral cut of the sale.
+ BuyNft{offering_id:u64,nft_address:String,token_id:Option<String>,max_price:Option<Uint128>,proof:Option<Vec<HexBinary>>,referrer:Option<String>},
+ WithdrawNft{offering_id:u64,nft_address:String},
+ UpdatePrice{nft_address:String,offering_id:u64,list_price:Asset},
+ /// Closes expired offerings among the `limit` that follow `start_after`.
+ /// The `last_scanned` attribute is the cursor for the next call.
+ PruneExpired{nft_address:String,start_after:Option<u64>,limit:Option<u32>},
+ /// Lists a token the sender keeps, after approving it to the marketplace.
+ ListNft{nft_address:String,token_id:String,price:Asset},
+ /// Removes approval-based offerings whose seller no longer owns or has
+ /// approved the token. See `QueryMsg::GetStaleOfferings`.
+ PruneStale{nft_address:String,offering_ids:Vec<u64>},
+ /// Buys all `(nft_address, offering_id)` items with the single attached coin.
+ BuyMany{items:Vec<(String,u64)>,max_total:Uint128},
+ /// Registers a token to be minted to its first buyer. Owner only.
+ AddMintTemplate{nft_address:String,token_id:String,token_uri:Option<String>,price:Asset},
+ RemoveMintTemplate{nft_address:String,template_id:u64},
+ BuyMint{nft_address:String,template_id:u64},
+ /// Sets up the primary sale of a collection. Phases must not overlap. Owner only.
+ SetLaunch{nft_address:String,phases:Vec<LaunchPhase>,supply_cap:u64,base_token_uri:Option<String>},
+ LaunchMint{nft_address:String,quantity:u32,proof:Option<Vec<HexBinary>>},
+ PlaceBid{nft_address:String,auction_id:u64},
+ SettleAuction{nft_address:String,auction_id:u64},
+ CancelAuction{nft_address:String,auction_id:u64},
+ MakeOffer{nft_address:String,token_id:String,expires_at:u64},
+ /// Accepts an offer on a token the sender has listed here. Unlisted tokens
+ /// accept through `ReceiveNft` with `Cw721HookMsg::AcceptOffer`.
+ AcceptOffer{nft_address:String,offering_id:u64,offer_id:u64},
+ CancelOffer{nft_address:String,token_id:String,offer_id:u64},
+ PlaceCollectionBid{nft_address:String,price:Uint128,quantity:u64,traits:Option<Vec<Trait>>},
+ CancelCollectionBid{nft_address:String,bid_id:u64},
+ /// Registers a bundle; each item is then escrowed with `Cw721HookMsg::DepositBundle`.
+ CreateBundle{price:Asset,items:Vec<BundleItem>},
+ BuyBundle{bundle_id:u64},
+ CancelBundle{bundle_id:u64},
+ /// Opens a swap escrowing the attached native funds. Offered NFTs are sent in
+ /// with `Cw721HookMsg::DepositSwap` and cw20 amounts with `Cw20HookMsg::FundSwap`.
+ CreateSwap{offered_nfts:Vec<NftRef>,wanted_nfts:Vec<NftRef>,wanted_funds:Vec<PaymentAsset>,counterparty:Option<String>},
+ /// Wanted native funds are attached; wanted NFTs and cw20 amounts must be
+ /// approved to the marketplace beforehand.
+ TakeSwap{swap_id:u64},
+ CancelSwap{swap_id:u64},
  ChangeOwner{address:String},
+ SetOracle{address:Option<String>},
+ /// Sets the global protocol fee, or the override of `nft_address` (`None` clears it).
+ SetProtocolFee{nft_address:Option<String>,fee:Option<Decimal>},
+ SetTreasury{address:String},
+ /// Sends the accrued fees in `denoms`, or in every denom, to the treasury.
+ WithdrawFees{denoms:Option<Vec<String>>},
+ SetPullPayments{nft_address:String,enabled:bool},
+ /// Sets the global referral cut, or the override of `nft_address` (`None` clears it).
+ SetReferralFee{nft_address:Option<String>,bps:Option<u64>},
+ /// Takes royalties from the collection's cw2981 `RoyaltyInfo` instead of `MEMBERS`.
+ SetRoyaltySource{nft_address:String,cw2981:bool},
+ /// Pays out the sender's pending balances in `assets`, or in every denom.
+ Claim{assets:Option<Vec<String>>},
+ ClaimFor{address:String,assets:Option<Vec<String>>},
  AddTokenAddress{symbol:String,address:String},
  AddCollection{royalty_portion:Decimal,members:Vec<UserInfo>,nft_address:String,offering_id:u64,sale_id:u64},
  UpdateCollection{royalty_portion:Decimal,members:Vec<UserInfo>,nft_address:String},
@@ -30,6 +86,10 @@ pub enum ExecuteMsg {
  SetSaleHistory{address:String,history:Vec<SaleInfo>}
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MigrateMsg {}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
@@ -38,23 +98,94 @@ pub enum QueryMsg {
     GetMembers{address:String},
     GetOfferingId{address:String},
     GetSaleHistory{address:String,id:Vec<String>},
-    GetOfferingPage{id :Vec<String>,address:String },
+    GetOfferingPage{id :Vec<u64>,address:String },
     GetTradingInfo{address:String},
     GetCollectionInfo{address:String},
     GetTvl{address:String,symbol:String},
-    GetTvlAll{address:String,symbols:Vec<String>}
+    GetTvlAll{address:String,symbols:Vec<String>},
+    GetAuctions{address:String,start_after:Option<u64>,limit:Option<u32>},
+    GetCurrentPrice{address:String,offering_id:u64},
+    GetOffers{address:String,token_id:String,start_after:Option<u64>,limit:Option<u32>},
+    GetCollectionBids{address:String,start_after:Option<u64>,limit:Option<u32>},
+    GetBundles{start_after:Option<u64>,limit:Option<u32>},
+    GetSwaps{start_after:Option<u64>,limit:Option<u32>},
+    GetStaleOfferings{address:String,start_after:Option<u64>,limit:Option<u32>},
+    GetMintTemplates{address:String,start_after:Option<u64>,limit:Option<u32>},
+    GetLaunch{address:String},
+    GetLaunchMinted{address:String,phase:u32,wallet:String},
+    /// Protocol fees awaiting withdrawal, one `Asset` per denom.
+    GetAccruedFees{},
+    GetPendingPayments{address:String},
+    /// Sale volume routed by `referrer`, one `Asset` per denom.
+    GetReferralVolume{referrer:String}
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct SellNft {
     pub list_price: Asset,
+    /// Block time (seconds) from which the NFT can be bought.
+    pub starts_at: Option<u64>,
+    /// Block time (seconds) at which the listing expires.
+    pub expires_at: Option<u64>,
+    /// Sell as a Dutch auction whose price starts at `list_price` and declines.
+    pub price_decay: Option<PriceDecay>,
+    /// Private sale to this buyer only, at a price agreed off-chain.
+    pub reserved_for: Option<String>,
+    /// Also accept these currencies, e.g. `list_price` in JUNO or 500 HOPE.
+    pub alt_prices: Option<Vec<Asset>>,
+    /// Quote the amounts in this reference unit instead, e.g. "uusd". Buyers
+    /// then have to pass a `max_price`.
+    pub peg_unit: Option<String>,
+    /// Also sell to any buyer proven to be in this sha256 Merkle root.
+    pub reserved_root: Option<HexBinary>,
 }
 
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct BuyNft {
-    pub offering_id: String,
-    pub nft_address : String
+    pub offering_id: u64,
+    pub nft_address : String,
+    /// Token the buyer expects to receive; the purchase fails if the offering holds another one.
+    pub token_id: Option<String>,
+    /// Highest price the buyer accepts, in the offering's denom.
+    pub max_price: Option<Uint128>,
+    pub proof: Option<Vec<HexBinary>>,
+    pub referrer: Option<String>
+}
+
+/// Hook messages accepted through `ReceiveNft` besides a plain `SellNft`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw721HookMsg {
+    StartAuction(StartAuction),
+    AcceptOffer{offer_id:u64},
+    FillCollectionBid{bid_id:u64},
+    DepositBundle{bundle_id:u64},
+    DepositSwap{swap_id:u64},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StartAuction {
+    pub reserve_price: Asset,
+    /// Seconds from now until bidding closes.
+    pub duration: u64,
+    pub min_bid_increment: Uint128,
+    pub extension_window: u64,
+}
+
+/// Hook messages accepted through `Receive` besides a plain `BuyNft`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20HookMsg {
+    PlaceBid{nft_address:String,auction_id:u64},
+    MakeOffer{nft_address:String,token_id:String,expires_at:u64},
+    PlaceCollectionBid{nft_address:String,price:Uint128,quantity:u64,traits:Option<Vec<Trait>>},
+    BuyBundle{bundle_id:u64},
+    FundSwap{swap_id:u64},
+    BuyMany{items:Vec<(String,u64)>,max_total:Uint128},
+    BuyMint{nft_address:String,template_id:u64},
+    LaunchMint{nft_address:String,quantity:u32,proof:Option<Vec<HexBinary>>},
 }
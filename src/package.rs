@@ -1,13 +1,26 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use crate::state::Asset;
+use cosmwasm_std::{Decimal, HexBinary, Uint128};
+use crate::state::{Asset, Auction, Bundle, CollectionBid, MintTemplate, Offer, PriceDecay, Swap};
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 pub struct QueryOfferingsResult {
-    pub id: String,
+    pub id: u64,
     pub token_id: String,
     pub list_price: Asset,
     pub seller: String,
+    pub starts_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub price_decay: Option<PriceDecay>,
+    pub reserved_for: Option<String>,
+    #[serde(default)]
+    pub non_custodial: bool,
+    #[serde(default)]
+    pub alt_prices: Vec<Asset>,
+    #[serde(default)]
+    pub peg_unit: Option<String>,
+    #[serde(default)]
+    pub reserved_root: Option<HexBinary>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -15,4 +28,74 @@ pub struct OfferingsResponse {
     pub offerings: Vec<QueryOfferingsResult>,
 }
 
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct QueryAuctionsResult {
+    pub id: u64,
+    pub auction: Auction,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct QueryOffersResult {
+    pub id: u64,
+    pub offer: Offer,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct QueryCollectionBidsResult {
+    pub id: u64,
+    pub bid: CollectionBid,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct QueryBundlesResult {
+    pub id: u64,
+    pub bundle: Bundle,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct QuerySwapsResult {
+    pub id: u64,
+    pub swap: Swap,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct QueryMintTemplatesResult {
+    pub id: u64,
+    pub template: MintTemplate,
+}
+
+/// Query interface the configured price oracle must implement.
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OracleQueryMsg {
+    /// Returns `OraclePriceResponse`
+    Price { base: String, quote: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct OraclePriceResponse {
+    /// Amount of `quote` worth one unit of `base`.
+    pub rate: Decimal,
+}
+
+/// cw2981 royalty query, sent to the NFT contract as a cw721-base extension.
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw2981QueryMsg {
+    Extension { msg: Cw2981Query },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw2981Query {
+    /// Returns `RoyaltiesInfoResponse`
+    RoyaltyInfo { token_id: String, sale_price: Uint128 },
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+pub struct RoyaltiesInfoResponse {
+    pub address: String,
+    pub royalty_amount: Uint128,
+}
+
 // THIS FILE SHOULD BE EXTRACTED TO ITS OWN PACKAGE PROJECT LIKE CW20 OR CW721
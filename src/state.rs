@@ -1,22 +1,57 @@
-use cosmwasm_std::{Uint128, Decimal};
+use cosmwasm_std::{Uint128, Decimal, HexBinary};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use cw_storage_plus::{Item,Map};
+use std::fmt;
+use crate::asset::{Asset as PaymentAsset, AssetInfo as PaymentAssetInfo};
 
 pub const CONFIG: Item<State> = Item::new("config_state");
 pub const MEMBERS : Map<&str,Vec<UserInfo>> = Map::new("config_members");
-pub const OFFERINGS: Map<(&str,&str), Offering> = Map::new("offerings");
+pub const OFFERINGS: Map<(&str,u64), Offering> = Map::new("offerings");
+/// Offerings as they were keyed before offering IDs became `u64`. Only read by `migrate`.
+pub const LEGACY_OFFERINGS: Map<(&str,&str), Offering> = Map::new("offerings");
 pub const SALEHISTORY : Map<(&str,&str), SaleInfo> = Map::new("sale");
 pub const PRICEINFO : Map<&str,PriceInfo> = Map::new("price_info");
 pub const COLLECTIONINFO : Map<&str, CollectionInfo> = Map::new("collection_info");
 pub const TOKENADDRESS : Map<&str, String> = Map::new("token_address");
 pub const TVL:Map<(&str,&str),Uint128> = Map::new("tvl_config");
+/// Protocol fees collected per denom and not yet withdrawn to the treasury.
+pub const ACCRUED_FEES: Map<&str, Uint128> = Map::new("accrued_fees");
+/// Proceeds credited to (address, denom) on pull-payment collections, paid
+/// out on `Claim`.
+pub const PENDING_PAYMENTS: Map<(&str,&str), Uint128> = Map::new("pending_payments");
+/// Sale volume routed by each (referrer, denom).
+pub const REFERRAL_VOLUME: Map<(&str,&str), Uint128> = Map::new("referral_volume");
+pub const AUCTIONS: Map<(&str,u64), Auction> = Map::new("auctions");
+pub const OFFERS: Map<(&str,&str,u64), Offer> = Map::new("offers");
+pub const COLLECTION_BIDS: Map<(&str,u64), CollectionBid> = Map::new("collection_bids");
+pub const BUNDLES: Map<u64, Bundle> = Map::new("bundles");
+pub const LAST_BUNDLE_ID: Item<u64> = Item::new("last_bundle_id");
+pub const SWAPS: Map<u64, Swap> = Map::new("swaps");
+pub const MINT_TEMPLATES: Map<(&str,u64), MintTemplate> = Map::new("mint_templates");
+pub const LAUNCHES: Map<&str, Launch> = Map::new("launches");
+/// Tokens minted per (collection, phase index, wallet)
+pub const LAUNCH_MINTS: Map<(&str,u32,&str), u32> = Map::new("launch_mints");
+pub const LAST_SWAP_ID: Item<u64> = Item::new("last_swap_id");
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct State {
     pub owner:String,
     pub new : bool,
-   
+    /// Price oracle used to convert pegged offerings, see `package::OracleQueryMsg`.
+    #[serde(default)]
+    pub oracle: Option<String>,
+    /// Share of every sale kept by the marketplace, on top of the collection
+    /// royalty. Collections can override it.
+    #[serde(default)]
+    pub protocol_fee: Decimal,
+    /// Where `WithdrawFees` sends the accrued protocol fees.
+    #[serde(default)]
+    pub treasury: Option<String>,
+    /// Cut of a sale paid to the referrer passed with the purchase, in basis
+    /// points. Collections can override it.
+    #[serde(default)]
+    pub referral_bps: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -24,6 +59,48 @@ pub struct Offering {
     pub token_id: String,
     pub seller: String,
     pub list_price: Asset,
+    /// Block time (seconds) before which the offering cannot be bought.
+    pub starts_at: Option<u64>,
+    /// Block time (seconds) from which the offering can no longer be bought.
+    pub expires_at: Option<u64>,
+    /// Makes the offering a Dutch auction that starts at `list_price`.
+    pub price_decay: Option<PriceDecay>,
+    /// The only address allowed to buy. Reserved offerings are left out of
+    /// the listing queries.
+    #[serde(default)]
+    pub reserved_for: Option<String>,
+    /// The seller kept the token and approved the marketplace to transfer it.
+    #[serde(default)]
+    pub non_custodial: bool,
+    /// Other currencies the offering can be bought in, at a fixed amount each.
+    #[serde(default)]
+    pub alt_prices: Vec<Asset>,
+    /// Reference unit (e.g. "uusd") the price amounts are quoted in. They are
+    /// converted to the payment denom through the oracle at purchase.
+    #[serde(default)]
+    pub peg_unit: Option<String>,
+    /// sha256 Merkle root of further buyers allowed besides `reserved_for`.
+    #[serde(default)]
+    pub reserved_root: Option<HexBinary>,
+}
+
+impl Offering {
+    /// Reserved offerings are left out of the listing queries.
+    pub fn is_reserved(&self) -> bool {
+        self.reserved_for.is_some() || self.reserved_root.is_some()
+    }
+}
+
+/// Declining price of a Dutch auction. The price falls from the offering's
+/// `list_price` to `end_price` between `start_time` and `end_time`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PriceDecay {
+    pub end_price: Uint128,
+    pub start_time: u64,
+    pub end_time: u64,
+    /// Drop the price once every `step_seconds` instead of every second.
+    pub step_seconds: Option<u64>,
 }
 
 
@@ -33,6 +110,12 @@ pub struct Asset {
     pub amount:Uint128
 }
 
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.amount, self.denom)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct UserInfo {
@@ -50,7 +133,20 @@ pub struct SaleInfo {
     pub amount:Uint128,
     pub time : u64,
     pub nft_address:String,
-    pub token_id:String
+    pub token_id:String,
+    /// Where the royalty of a secondary sale was taken from. `None` for
+    /// primary sales and sales recorded before sources were tracked.
+    #[serde(default)]
+    pub royalty_source: Option<RoyaltySource>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RoyaltySource {
+    /// `royalty_portion` split over the collection `MEMBERS`
+    Members,
+    /// Recipient and amount returned by the token's cw2981 `RoyaltyInfo`
+    Cw2981,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -62,12 +158,37 @@ pub struct PriceInfo {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-
 pub struct CollectionInfo{
     pub nft_address :String,
+    /// Number of live offerings in the collection.
     pub offering_id:u64,
     pub royalty_portion:Decimal,
-    pub sale_id : u64
+    pub sale_id : u64,
+    /// Last offering ID handed out. IDs are never reused, so this only grows.
+    #[serde(default)]
+    pub last_offering_id:u64,
+    #[serde(default)]
+    pub last_auction_id:u64,
+    #[serde(default)]
+    pub last_offer_id:u64,
+    #[serde(default)]
+    pub last_collection_bid_id:u64,
+    #[serde(default)]
+    pub last_mint_template_id:u64,
+    /// Replaces the global `State::protocol_fee` for this collection.
+    #[serde(default)]
+    pub protocol_fee: Option<Decimal>,
+    /// Credit sale proceeds and royalties to the claim ledger instead of
+    /// sending them with the purchase.
+    #[serde(default)]
+    pub pull_payments: bool,
+    /// Replaces the global `State::referral_bps` for this collection.
+    #[serde(default)]
+    pub referral_bps: Option<u64>,
+    /// Ask the NFT contract for each token's cw2981 royalty at sale time,
+    /// falling back to `MEMBERS` when it does not answer.
+    #[serde(default)]
+    pub cw2981_royalties: bool
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -76,3 +197,184 @@ pub struct TvlInfo {
    pub denom : String,
    pub amount: Uint128
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Auction {
+    pub token_id: String,
+    pub seller: String,
+    /// Lowest acceptable first bid; its denom is the currency of the auction.
+    pub reserve_price: Asset,
+    /// The coin or cw20 contract bids are escrowed in, fixed at the start.
+    pub payment: PaymentAssetInfo,
+    pub min_bid_increment: Uint128,
+    /// Block time (seconds) after which no bids are accepted.
+    pub end_time: u64,
+    /// A bid within this many seconds of `end_time` pushes the end out to
+    /// `extension_window` seconds after the bid. At most the duration.
+    pub extension_window: u64,
+    /// Escrowed by the contract until it is outbid or the auction settles.
+    pub highest_bid: Option<Bid>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Bid {
+    pub bidder: String,
+    pub amount: Uint128,
+}
+
+/// Funds a buyer escrowed for a specific token, listed or not.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Offer {
+    pub buyer: String,
+    pub price: Asset,
+    /// The coin or cw20 contract the escrow was received in.
+    pub payment: PaymentAssetInfo,
+    /// Block time (seconds) from which the offer can no longer be accepted.
+    pub expires_at: u64,
+}
+
+/// Bid for any token of a collection. The contract escrows `price` for each
+/// of the `quantity` tokens still wanted.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CollectionBid {
+    pub buyer: String,
+    /// Price paid per token.
+    pub price: Asset,
+    /// The coin or cw20 contract the escrow was received in.
+    pub payment: PaymentAssetInfo,
+    pub quantity: u64,
+    /// Traits a token's metadata must carry to fill the bid. Empty for a floor bid.
+    #[serde(default)]
+    pub traits: Vec<Trait>,
+}
+
+/// Metadata attribute, as stored by on-chain metadata cw721 contracts.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Trait {
+    pub trait_type: String,
+    pub value: String,
+}
+
+/// The part of a token's on-chain metadata extension we read. cw721-base's own
+/// `Extension` is `Option<Empty>`, so collections with traits use this shape.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct Metadata {
+    pub attributes: Option<Vec<Trait>>,
+}
+
+/// Several tokens sold together for one price. The bundle can only be bought
+/// once every item has been sent to the contract.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Bundle {
+    pub seller: String,
+    pub price: Asset,
+    /// The coin or cw20 contract the bundle is paid in, fixed at creation.
+    pub payment: PaymentAssetInfo,
+    pub items: Vec<BundleItem>,
+}
+
+impl Bundle {
+    pub fn is_active(&self) -> bool {
+        self.items.iter().all(|item| item.deposited)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BundleItem {
+    pub nft_address: String,
+    pub token_id: String,
+    /// Share of the bundle price booked against this token, and so paid out
+    /// under its collection's royalty. Allocations sum to 1.
+    pub allocation: Decimal,
+    #[serde(default)]
+    pub deposited: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct NftRef {
+    pub nft_address: String,
+    pub token_id: String,
+}
+
+/// Peer-to-peer trade of NFTs and funds. The maker's side is escrowed in the
+/// contract; the taker's side is pulled in when the swap is taken.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Swap {
+    pub maker: String,
+    /// Only this address may take the swap, if set.
+    pub counterparty: Option<String>,
+    pub offered_nfts: Vec<NftRef>,
+    /// Offered NFTs received so far.
+    pub escrowed_nfts: Vec<NftRef>,
+    /// Native and cw20 amounts escrowed by the maker.
+    pub offered_funds: Vec<PaymentAsset>,
+    pub wanted_nfts: Vec<NftRef>,
+    pub wanted_funds: Vec<PaymentAsset>,
+}
+
+impl Swap {
+    pub fn is_active(&self) -> bool {
+        self.escrowed_nfts.len() == self.offered_nfts.len()
+    }
+}
+
+/// A token that is only minted once bought. The marketplace must be the
+/// minter of the cw721-base collection.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MintTemplate {
+    pub token_id: String,
+    pub token_uri: Option<String>,
+    pub price: Asset,
+}
+
+/// Token ids of launch mints start with this, so they cannot collide with
+/// lazy-mint templates.
+pub const LAUNCH_TOKEN_PREFIX: &str = "launch.";
+
+/// Primary sale of a collection the marketplace mints for. Tokens are
+/// numbered from 1 in mint order, as `launch.1`, `launch.2`, ...
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Launch {
+    pub phases: Vec<LaunchPhase>,
+    pub supply_cap: u64,
+    pub base_token_uri: Option<String>,
+    #[serde(default)]
+    pub minted: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LaunchPhase {
+    pub name: String,
+    pub price: Asset,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub per_wallet_limit: Option<u32>,
+    /// Open to everyone when both `allowlist` and `merkle_root` are `None`
+    pub allowlist: Option<Vec<String>>,
+    /// sha256 Merkle root of allowlisted wallets, checked against the proof
+    /// sent with the mint.
+    #[serde(default)]
+    pub merkle_root: Option<HexBinary>,
+}
+
+impl Launch {
+    /// Index and phase running at `now`, if any.
+    pub fn active_phase(&self, now: u64) -> Option<(u32, &LaunchPhase)> {
+        self.phases
+            .iter()
+            .enumerate()
+            .find(|(_, phase)| phase.start_time <= now && now < phase.end_time)
+            .map(|(i, phase)| (i as u32, phase))
+    }
+}